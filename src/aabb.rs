@@ -62,6 +62,22 @@ impl Aabb {
         }
     }
 
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            0.5 * (self.x.min + self.x.max),
+            0.5 * (self.y.min + self.y.max),
+            0.5 * (self.z.min + self.z.max),
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        if dx < 0.0 || dy < 0.0 || dz < 0.0 {
+            return 0.0;
+        }
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
     pub fn axis(&self, n: usize) -> Interval {
         if n == 1 {
             self.y
@@ -74,7 +90,7 @@ impl Aabb {
 
     pub fn hit(&self, r: &Ray, ray_t: &Interval) -> bool {
         let mut ray_t = *ray_t;
-        for a in 0..3 as usize {
+        for a in 0..3 {
             let inv_d = 1.0 / (&r.dir)[a];
             let orig = (&r.orig)[a];
 
@@ -99,6 +115,27 @@ impl Aabb {
         }
         true
     }
+
+    /// Slab test against a precomputed `1 / dir`, for testing one ray against many boxes.
+    pub fn hit_inv_dir(&self, orig: &Point3, inv_dir: &Vector3, mut ray_t: Interval) -> bool {
+        for a in 0..3 {
+            let inv_d = inv_dir[a];
+            let mut t0 = (self.axis(a).min - orig[a]) * inv_d;
+            let mut t1 = (self.axis(a).max - orig[a]) * inv_d;
+
+            if inv_d < 0.0 {
+                mem::swap(&mut t0, &mut t1);
+            }
+
+            ray_t.min = ray_t.min.max(t0);
+            ray_t.max = ray_t.max.min(t1);
+
+            if ray_t.max <= ray_t.min {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 impl std::ops::Add<&Vector3> for &Aabb {
@@ -0,0 +1,85 @@
+use crate::{color::Color, hittable::HitRecord, random, ray::Ray};
+
+/// An auxiliary image recorded at the first surface each camera ray hits, averaged over the
+/// samples of a pixel like the image itself.
+///
+/// Normals, positions and depths are stored as they are, so they are only faithful in the float
+/// formats; the ID outputs give each object or material its own color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// The material's base color, or the background where nothing was hit.
+    Albedo,
+    /// The shading normal, facing the camera.
+    Normal,
+    /// The distance from the camera along the ray.
+    Depth,
+    /// The world-space position.
+    Position,
+    /// The texture coordinates in red and green.
+    Uv,
+    /// The object that was hit. Every shape, mesh, box, medium and instance in a scene has its
+    /// own ID, however it is grouped.
+    ObjectId,
+    /// The material that was hit. Materials with the same parameters share a color.
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::Uv,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+
+    /// The name used on the command line and in the names of the files written.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.into_iter().find(|aov| aov.name() == name)
+    }
+
+    /// The value for camera ray `r`, which hit `rec` or, if that is `None`, the background.
+    pub fn value(&self, r: &Ray, rec: Option<&HitRecord>, background: &Color) -> Color {
+        let Some(rec) = rec else {
+            return match self {
+                Aov::Albedo => background.clone(),
+                _ => Color::new(0.0, 0.0, 0.0),
+            };
+        };
+        match self {
+            Aov::Albedo => rec.material.albedo(rec),
+            Aov::Normal => Color::new(rec.normal.x, rec.normal.y, rec.normal.z),
+            Aov::Depth => {
+                // camera rays aren't normalized
+                let d = rec.t * r.dir.length();
+                Color::new(d, d, d)
+            }
+            Aov::Position => Color::new(rec.p.x, rec.p.y, rec.p.z),
+            Aov::Uv => Color::new(rec.u, rec.v, 0.0),
+            Aov::ObjectId => id_color(rec.object as u64),
+            Aov::MaterialId => id_color(rec.material.key()),
+        }
+    }
+}
+
+/// A color for `id`, bright enough to tell from the black background and unrelated to the
+/// colors of neighbouring IDs.
+fn id_color(id: u64) -> Color {
+    let h = random::hash(&[id]);
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xffff) as f64 / 65535.0;
+    Color::new(channel(0), channel(16), channel(32))
+}
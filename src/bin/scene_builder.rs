@@ -2,7 +2,7 @@ use rand::Rng;
 use raytracing_rs::{
     color::Color,
     point3::Point3,
-    scene::{Camera, Geometry, Material, Scene, Texture},
+    scene::{Camera, Geometry, Material, Scene, Texture, Transform},
     vector3::Vector3,
 };
 
@@ -21,15 +21,14 @@ fn final_scene(image_width: u32, samples_per_pixel: usize, max_depth: u32) -> Sc
             let w = 100.0;
             let x0 = -1000.0 + i * w;
             let z0 = -1000.0 + j * w;
-            let y0 = 0.0;
-            let x1 = x0 + w;
             let y1 = rng.gen_range(1.0..101.0);
-            let z1 = z0 + w;
 
-            boxes1.push(Geometry::new_box(
-                Point3::new(x0, y0, z0),
-                Point3::new(x1, y1, z1),
-                ground.clone(),
+            boxes1.push(Geometry::new_instance(
+                "ground_box",
+                vec![
+                    Transform::Scale(Vector3::new(w, y1, w)),
+                    Transform::Translate(Vector3::new(x0, 0.0, z0)),
+                ],
             ));
         }
     }
@@ -100,7 +99,7 @@ fn final_scene(image_width: u32, samples_per_pixel: usize, max_depth: u32) -> Sc
 
     for _ in 0..1000 {
         boxes2.push(Geometry::new_sphere(
-            Point3::random(0.0, 165.0),
+            Point3::random(0.0, 165.0, &mut rng),
             10.0,
             white.clone(),
         ));
@@ -125,7 +124,14 @@ fn final_scene(image_width: u32, samples_per_pixel: usize, max_depth: u32) -> Sc
         Color::new(0.0, 0.0, 0.0),
     );
 
-    Scene::new(world, lights, cam)
+    Scene::new(world, lights, cam).with_definition(
+        "ground_box",
+        Geometry::new_box(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            ground,
+        ),
+    )
 }
 
 #[allow(dead_code)]
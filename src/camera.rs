@@ -1,17 +1,72 @@
-use indicatif::{ParallelProgressIterator, ProgressStyle};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::{Add, Div, Mul},
+    path::Path,
+};
+
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
 use rand::Rng;
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    color::Color, hittable::Hittable, interval::Interval, material::ScatterRecord, pdf,
-    point3::Point3, ray::Ray, vector3::Vector3,
+    aov::Aov,
+    color::Color,
+    film::{read_f64, read_u32, read_u64, Film, FilmTile},
+    filter::Filter,
+    framebuffer::Framebuffer,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::ScatterRecord,
+    output, pdf,
+    point3::Point3,
+    random,
+    ray::Ray,
+    sampler::{self, SampleStream, Sampler},
+    spectrum::{Spectrum, Wavelengths},
+    tile::{self, Tile, TileOrder},
+    tonemap::ToneMap,
+    vector3::Vector3,
 };
 
+/// Tiles rendered between merges into the film, which bounds the memory held in unmerged tiles.
+const TILES_PER_BATCH: usize = 256;
+
+/// Below this luminance the error of a pixel is measured absolutely rather than relative to its
+/// brightness, so nearly black pixels don't soak up the whole sample budget.
+const MIN_LUMINANCE: f64 = 0.01;
+
+/// Spend the samples where the image is noisiest instead of the same number in every pixel.
+///
+/// `samples_per_pixel` becomes the average budget over the image. Samples are taken in batches
+/// of `min_samples`, and every pixel takes one batch in the first pass. A pixel stops once the
+/// standard error of its mean luminance, relative to that mean, is below `threshold`, or once
+/// it has had `max_samples`. Each later pass gives a batch to the unfinished pixels with the
+/// highest error, for as long as the budget lasts, so the samples the converged pixels don't
+/// need go to the noisy ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub min_samples: usize,
+    pub max_samples: usize,
+}
+
 pub struct Camera {
     image_width: u32,
     image_height: u32,
     samples_per_pixel: usize,
     max_depth: u32,
+    seed: u64,
+    sampler: Sampler,
+    filter: Filter,
+    adaptive: Option<Adaptive>,
+    tile_size: u32,
+    tile_order: TileOrder,
+    region: Option<Tile>,
+    aovs: Vec<Aov>,
+    tone_map: ToneMap,
+    spectral: bool,
+    scene_key: u64,
 
     background: Color,
 
@@ -26,6 +81,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         image_width: u32,
         aspect_ratio: f64,
@@ -70,6 +126,17 @@ impl Camera {
             image_height,
             samples_per_pixel,
             max_depth,
+            seed: 0,
+            sampler: Sampler::default(),
+            filter: Filter::default(),
+            adaptive: None,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            region: None,
+            aovs: Vec::new(),
+            tone_map: ToneMap::default(),
+            spectral: false,
+            scene_key: 0,
             background,
             defocus_angle,
             center,
@@ -81,96 +148,1157 @@ impl Camera {
         }
     }
 
-    pub fn render(&self, world: &Hittable, lights: &Hittable) {
-        let bar_style = ProgressStyle::with_template("{bar:40} {pos}/{len} {eta}").unwrap();
-        println!("P3\n{} {}\n255", self.image_width, self.image_height);
-        let image: Vec<Vec<_>> = (0..self.image_height)
-            .into_par_iter()
-            .progress_with_style(bar_style)
-            .map(|j| {
-                // eprint!("\rScanlines remaining: {}   ", self.image_height - j);
-                (0..self.image_width)
-                    .into_par_iter()
-                    .map(|i| {
-                        (0..self.samples_per_pixel)
-                            .into_par_iter()
-                            .map(|_| {
-                                let r = self.get_ray(i, j);
-                                self.ray_color(&r, self.max_depth, world, lights)
-                            })
-                            .reduce(|| Color::new(0.0, 0.0, 0.0), |a, b| a + b)
-                    })
-                    .collect()
-            })
-            .collect();
-        image.iter().for_each(|row| {
-            row.iter().for_each(|c| {
-                println!("{}", c.write(self.samples_per_pixel));
-            })
-        })
+    /// Renders with a different, but equally reproducible, set of random samples.
+    pub fn with_seed(self, seed: u64) -> Self {
+        Camera { seed, ..self }
     }
 
-    fn ray_color(&self, r: &Ray, depth: u32, world: &Hittable, lights: &Hittable) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
+    pub fn with_sampler(self, sampler: Sampler) -> Self {
+        Camera { sampler, ..self }
+    }
+
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Camera { filter, ..self }
+    }
+
+    /// Samples adaptively, with `samples_per_pixel` as the average over the image. It should be at
+    /// least `min_samples`, or some pixels never get their first batch.
+    pub fn with_adaptive(self, adaptive: Adaptive) -> Self {
+        Camera {
+            adaptive: Some(adaptive),
+            ..self
         }
+    }
 
-        world
-            .hit(r, Interval::new(0.001, f64::MAX))
-            .and_then(|rec| {
-                let color_from_emission = rec.material.emitted(&r, &rec, rec.u, rec.v, &rec.p);
-                rec.material
-                    .scatter(r, &rec)
-                    .and_then(|srec| match srec {
-                        ScatterRecord::Ray(attenuation, scatter) => {
-                            Some(attenuation * self.ray_color(&scatter, depth - 1, world, lights))
-                        }
-                        ScatterRecord::Pdf(attenuation, rec_pdf) => {
-                            let light_pdf = pdf::Hittable::new(&lights, rec.p.clone());
-                            let p = pdf::Mixture::new(&light_pdf, &rec_pdf);
-                            let scattered = Ray::new(rec.p.clone(), p.generate(), r.time);
-                            let pdf_val = p.value(&scattered.dir);
+    pub fn with_tiles(self, tile_size: u32, tile_order: TileOrder) -> Self {
+        Camera {
+            tile_size,
+            tile_order,
+            ..self
+        }
+    }
+
+    /// Renders only the pixels in `region`, which must lie within the image, and crops the image
+    /// to it.
+    pub fn with_region(self, region: Tile) -> Self {
+        Camera {
+            region: Some(region),
+            ..self
+        }
+    }
+
+    /// Records `aovs` at the first hit of every sample as well as the image.
+    pub fn with_aovs(self, aovs: Vec<Aov>) -> Self {
+        Camera { aovs, ..self }
+    }
+
+    /// How the image is exposed and tone mapped when it's written. It doesn't change the render.
+    pub fn with_tone_map(self, tone_map: ToneMap) -> Self {
+        Camera { tone_map, ..self }
+    }
+
+    /// Traces every sample at a handful of wavelengths instead of in RGB, so that dispersive glass
+    /// splits light into its colors.
+    pub fn with_spectral(self, spectral: bool) -> Self {
+        Camera { spectral, ..self }
+    }
 
-                            let scattering_pdf = rec.material.scattering_pdf(r, &rec, &scattered);
-                            let sample_color = self.ray_color(&scattered, depth - 1, world, lights);
+    /// Identifies the world being rendered, so that a render saved from another isn't resumed.
+    pub fn with_scene_key(self, scene_key: u64) -> Self {
+        Camera { scene_key, ..self }
+    }
+
+    pub fn tone_map(&self) -> &ToneMap {
+        &self.tone_map
+    }
+
+    pub fn image_width(&self) -> u32 {
+        self.image_width
+    }
+
+    pub fn image_height(&self) -> u32 {
+        self.image_height
+    }
 
-                            let color_from_scatter =
-                                (attenuation * scattering_pdf * sample_color) / pdf_val;
+    /// Renders every pass in turn. Every sample draws from its own generator, and tiles are
+    /// rendered into their own film tiles that are merged in order, so the image depends only on
+    /// the seed and not on how the work is split between threads.
+    pub fn render(&self, world: &Hittable, lights: &Hittable) -> Framebuffer {
+        let mut render = self.start(None);
+        while !render.is_done() {
+            render.pass(world, lights);
+        }
+        render.image()
+    }
+
+    /// Starts a render that adds `samples_per_pass` samples to every pixel in each pass over the
+    /// image, or all of them in a single pass if `None`. Adaptive renders add a batch of
+    /// `min_samples` to the noisiest unconverged pixels instead.
+    pub fn start(&self, samples_per_pass: Option<usize>) -> Render<'_> {
+        self.begin(
+            samples_per_pass,
+            self.film(),
+            vec![PixelStats::default(); (self.image_width * self.image_height) as usize],
+            0,
+            self.grid(),
+        )
+    }
 
-                            Some(&color_from_emission + color_from_scatter)
+    /// Continues the render saved by `Render::save` at `path`. The state must come from the same
+    /// scene and a camera with the same view, resolution, seed, sampler and filter; the sample
+    /// counts may differ, so a render can be resumed with more samples than it was started with.
+    /// The new samples are stratified on the grid the render was started with.
+    pub fn resume<P: AsRef<Path>>(
+        &self,
+        samples_per_pass: Option<usize>,
+        path: P,
+    ) -> io::Result<Render<'_>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header = StateHeader::read(&mut reader)?;
+        let expected = self.state_header(header.passes, header.grid);
+        let mismatch = if (header.width, header.height) != (expected.width, expected.height) {
+            Some(format!(
+                "it is {}x{} but this render is {}x{}",
+                header.width, header.height, expected.width, expected.height
+            ))
+        } else if header.seed != expected.seed {
+            Some(format!(
+                "it was rendered with seed {}, not {}",
+                header.seed, expected.seed
+            ))
+        } else if header.scene != expected.scene {
+            Some("it was rendered from a different scene".to_owned())
+        } else if header.view != expected.view {
+            Some(
+                "it was rendered with a different camera position, lens, depth or background"
+                    .to_owned(),
+            )
+        } else if header.settings != expected.settings {
+            Some(
+                "it was rendered with a different sampler, filter, AOVs or spectral mode"
+                    .to_owned(),
+            )
+        } else {
+            None
+        };
+        if let Some(mismatch) = mismatch {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, mismatch));
+        }
+
+        let mut film = self.film();
+        film.load(&mut reader)?;
+        let mut pixels =
+            vec![PixelStats::default(); (self.image_width * self.image_height) as usize];
+        for stats in &mut pixels {
+            stats.samples = read_u64(&mut reader)? as usize;
+            stats.mean = read_f64(&mut reader)?;
+            stats.m2 = read_f64(&mut reader)?;
+            stats.done = self.is_pixel_done(stats);
+        }
+
+        Ok(self.begin(
+            samples_per_pass,
+            film,
+            pixels,
+            header.passes as usize,
+            header.grid as usize,
+        ))
+    }
+
+    fn begin(
+        &self,
+        samples_per_pass: Option<usize>,
+        film: Film,
+        pixels: Vec<PixelStats>,
+        passes: usize,
+        grid: usize,
+    ) -> Render<'_> {
+        let samples_per_pass = samples_per_pass.unwrap_or(self.samples_per_pixel).max(1);
+        let region = self.region();
+        let tiles = self.tiles();
+
+        let remaining = match self.adaptive {
+            // the slowest pixel sets how many more passes there will be
+            None => {
+                let fewest = region_pixels(&pixels, self.image_width, region)
+                    .map(|p| p.samples)
+                    .min()
+                    .unwrap_or(0);
+                self.samples_per_pixel
+                    .saturating_sub(fewest)
+                    .div_ceil(samples_per_pass)
+            }
+            // as many as spending the rest of the budget evenly would take
+            Some(adaptive) => {
+                let n = (region.width() * region.height()) as usize;
+                let spent: usize = region_pixels(&pixels, self.image_width, region)
+                    .map(|p| p.samples)
+                    .sum();
+                (self.samples_per_pixel * n)
+                    .saturating_sub(spent)
+                    .div_ceil(adaptive.min_samples * n)
+            }
+        };
+        let bar = ProgressBar::new((remaining * tiles.len()) as u64)
+            .with_style(ProgressStyle::with_template("{bar:40} {pos}/{len} {eta}").unwrap());
+
+        let mut pixels = pixels;
+        let done = !self.schedule(
+            region_pixels_mut(&mut pixels, self.image_width, region),
+            samples_per_pass,
+        );
+        Render {
+            camera: self,
+            samples_per_pass,
+            done,
+            region,
+            tiles,
+            film,
+            pixels,
+            passes,
+            grid,
+            bar,
+        }
+    }
+
+    /// Takes the samples `schedule` gave the pixels of `tile`, whose statistics are `stats` row by
+    /// row, on stratified grids of `grid` samples, and returns the film tile they were splatted
+    /// into.
+    pub(crate) fn render_tile(
+        &self,
+        tile: Tile,
+        stats: &mut [PixelStats],
+        grid: usize,
+        world: &Hittable,
+        lights: &Hittable,
+    ) -> FilmTile {
+        let mut target = Target {
+            film: FilmTile::new(
+                tile,
+                self.image_width,
+                self.image_height,
+                self.filter,
+                &self.aovs,
+            ),
+            world,
+            lights,
+            grid,
+        };
+        let mut k = 0;
+        for j in tile.y0..tile.y1 {
+            for i in tile.x0..tile.x1 {
+                self.render_pixel(i, j, &mut stats[k], &mut target);
+                k += 1;
+            }
+        }
+        target.film
+    }
+
+    /// An empty film for this camera's image.
+    pub(crate) fn film(&self) -> Film {
+        Film::new(self.image_width, self.image_height, self.filter).with_aovs(self.aovs.clone())
+    }
+
+    /// The pixels being rendered: the region if one was set, otherwise the whole image.
+    pub(crate) fn region(&self) -> Tile {
+        self.region
+            .unwrap_or(Tile::new(0, 0, self.image_width, self.image_height))
+    }
+
+    /// The region split into tiles, in the order they are rendered and merged.
+    pub(crate) fn tiles(&self) -> Vec<Tile> {
+        tile::split(self.region(), self.tile_size, self.tile_order)
+    }
+
+    /// The number of samples in each of a pixel's stratified grids when starting a render.
+    pub(crate) fn grid(&self) -> usize {
+        // adaptive batches are each a complete grid
+        match self.adaptive {
+            None => self.samples_per_pixel,
+            Some(adaptive) => adaptive.min_samples,
+        }
+    }
+
+    fn state_header(&self, passes: u64, grid: u64) -> StateHeader {
+        StateHeader {
+            width: self.image_width,
+            height: self.image_height,
+            seed: self.seed,
+            scene: self.scene_key,
+            view: random::hash_str(&format!(
+                "{:?} {:?} {:?} {:?} {:?} {:?} {} {:?}",
+                self.center,
+                self.pixel00_loc,
+                self.pixel_delta_u,
+                self.pixel_delta_v,
+                self.defocus_disk_u,
+                self.defocus_disk_v,
+                self.max_depth,
+                self.background
+            )),
+            // the sums depend on the filter, which samples were taken on the sampler and on
+            // spectral mode, and the film's layout on the AOVs
+            settings: random::hash_str(&format!(
+                "{:?} {:?} {:?}{}",
+                self.sampler,
+                self.filter,
+                self.aovs,
+                if self.spectral { " spectral" } else { "" }
+            )),
+            grid,
+            passes,
+        }
+    }
+
+    fn is_pixel_done(&self, stats: &PixelStats) -> bool {
+        match self.adaptive {
+            None => stats.samples >= self.samples_per_pixel,
+            Some(adaptive) => {
+                stats.samples >= adaptive.max_samples
+                    || (stats.samples > 0 && stats.error() < adaptive.threshold)
+            }
+        }
+    }
+
+    /// Sets how many samples each of `pixels` takes in the next pass and returns whether any takes
+    /// some. Without adaptive sampling each takes up to `samples_per_pass` more until it has
+    /// `samples_per_pixel`. Adaptive pixels that haven't finished take a batch each, highest error
+    /// first, until the budget left of `samples_per_pixel` per pixel runs out. The schedule
+    /// depends only on the samples taken so far, so it is as reproducible as a fixed count.
+    fn schedule<'a>(
+        &self,
+        pixels: impl Iterator<Item = &'a mut PixelStats>,
+        samples_per_pass: usize,
+    ) -> bool {
+        let mut pixels: Vec<_> = pixels.collect();
+        match self.adaptive {
+            None => {
+                for p in &mut pixels {
+                    p.batch = self
+                        .samples_per_pixel
+                        .saturating_sub(p.samples)
+                        .min(samples_per_pass);
+                }
+            }
+            Some(adaptive) => {
+                let spent: usize = pixels.iter().map(|p| p.samples).sum();
+                let mut budget = (self.samples_per_pixel * pixels.len()).saturating_sub(spent);
+                // pixels without samples have no error yet and go first
+                let priority = |p: &PixelStats| {
+                    if p.samples == 0 {
+                        f64::INFINITY
+                    } else {
+                        p.error()
+                    }
+                };
+                let mut order: Vec<_> = (0..pixels.len()).filter(|&k| !pixels[k].done).collect();
+                // a stable sort, so equal errors keep the pixels' order
+                order.sort_by(|&a, &b| priority(pixels[b]).total_cmp(&priority(pixels[a])));
+
+                for p in &mut pixels {
+                    p.batch = 0;
+                }
+                for k in order {
+                    let batch = adaptive
+                        .min_samples
+                        .min(adaptive.max_samples - pixels[k].samples);
+                    // a pixel close to max_samples takes a smaller batch that may still fit
+                    if batch > budget {
+                        continue;
+                    }
+                    budget -= batch;
+                    pixels[k].batch = batch;
+                }
+            }
+        }
+        pixels.iter().any(|p| p.batch > 0)
+    }
+
+    /// Takes the samples `schedule` gave pixel (`i`, `j`) for this pass.
+    fn render_pixel(&self, i: u32, j: u32, stats: &mut PixelStats, target: &mut Target) {
+        let start = stats.samples;
+        for s in start..start + stats.batch {
+            let color = self.sample(i, j, s, target.grid, target);
+            stats.add(color.luminance());
+        }
+
+        stats.batch = 0;
+        stats.done = self.is_pixel_done(stats);
+    }
+
+    /// Traces sample `index` of pixel (`i`, `j`), splats it into the target's film and returns its
+    /// color.
+    fn sample(
+        &self,
+        i: u32,
+        j: u32,
+        index: usize,
+        samples_per_pixel: usize,
+        target: &mut Target,
+    ) -> Color {
+        let mut rng = self
+            .sampler
+            .stream(self.seed, i, j, index, samples_per_pixel);
+        let (u, v) = rng.get_2d();
+        let (x, y) = (i as f64 + u, j as f64 + v);
+        let r = self.get_ray(x, y, &mut rng);
+        let color = if self.max_depth == 0 {
+            Color::new(0.0, 0.0, 0.0)
+        } else {
+            let rec = target
+                .world
+                .hit(&r, Interval::new(0.001, f64::MAX), &mut rng);
+            if !self.aovs.is_empty() {
+                target
+                    .film
+                    .add_aovs(i, j, |aov| aov.value(&r, rec.as_ref(), &self.background));
+            }
+            let (depth, world, lights) = (self.max_depth, target.world, target.lights);
+            match r.wavelengths {
+                None => self.shade::<Color>(&r, rec, depth, world, lights, &mut rng),
+                Some(w) => {
+                    w.to_rgb(&self.shade::<Spectrum>(&r, rec, depth, world, lights, &mut rng))
+                }
+            }
+        };
+        target.film.splat(x, y, &color);
+        color
+    }
+
+    fn ray_color<L: Radiance>(
+        &self,
+        r: &Ray,
+        depth: u32,
+        world: &Hittable,
+        lights: &Hittable,
+        rng: &mut impl Rng,
+    ) -> L {
+        if depth == 0 {
+            return L::from_color(Color::new(0.0, 0.0, 0.0), r);
+        }
+
+        let rec = world.hit(r, Interval::new(0.001, f64::MAX), rng);
+        self.shade(r, rec, depth, world, lights, rng)
+    }
+
+    /// The light arriving along `r`, which hit `rec` or, if that is `None`, escaped.
+    fn shade<L: Radiance>(
+        &self,
+        r: &Ray,
+        rec: Option<HitRecord>,
+        depth: u32,
+        world: &Hittable,
+        lights: &Hittable,
+        rng: &mut impl Rng,
+    ) -> L {
+        rec.map(|rec| {
+            let color_from_emission =
+                L::from_color(rec.material.emitted(r, &rec, rec.u, rec.v, &rec.p), r);
+            rec.material
+                .scatter(r, &rec, rng)
+                .map(|srec| match srec {
+                    ScatterRecord::Ray(attenuation, scatter) => {
+                        let arriving: L = self.ray_color(&scatter, depth - 1, world, lights, rng);
+                        L::from_color(attenuation, r) * arriving.carry(r, &scatter)
+                    }
+                    ScatterRecord::Pdf(rec_pdf) => {
+                        let light_pdf = pdf::Hittable::new(lights, rec.p.clone());
+                        let p = pdf::Mixture::new(&light_pdf, &rec_pdf);
+                        let scattered = r.bounce(rec.p.clone(), p.generate(rng));
+                        let pdf_val = p.value(&scattered.dir);
+                        // a direction neither pdf could have drawn, like a microfacet reflection
+                        // that ends up below the surface
+                        if pdf_val <= 0.0 {
+                            return color_from_emission.clone();
                         }
-                    })
-                    .or(Some(color_from_emission))
-            })
-            .unwrap_or(self.background.clone())
+
+                        let sample_color: L =
+                            self.ray_color(&scattered, depth - 1, world, lights, rng);
+
+                        let color_from_scatter =
+                            L::from_color(rec.material.eval(r, &rec, &scattered), r) * sample_color
+                                / pdf_val;
+
+                        color_from_emission.clone() + color_from_scatter
+                    }
+                })
+                .unwrap_or(color_from_emission)
+        })
+        .unwrap_or_else(|| L::from_color(self.background.clone(), r))
     }
 
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let mut rng = rand::thread_rng();
-        let pixel_center =
-            &self.pixel00_loc + (i as f64 * &self.pixel_delta_u) + (j as f64 * &self.pixel_delta_v);
-        let pixel_sample = pixel_center + self.pixel_sample_square();
+    /// The ray through continuous film position (`x`, `y`). The caller has already drawn the film
+    /// position from `rng`; the lens position, the time and in spectral mode the wavelengths come
+    /// next, so those always use the same dimensions of the sample.
+    fn get_ray(&self, x: f64, y: f64, rng: &mut SampleStream) -> Ray {
+        // pixel00_loc is the centre of the first pixel, half a pixel in from the corner
+        let pixel_sample = &self.pixel00_loc
+            + ((x - 0.5) * &self.pixel_delta_u)
+            + ((y - 0.5) * &self.pixel_delta_v);
 
+        let lens = rng.get_2d();
         let orig = if self.defocus_angle <= 0.0 {
             self.center.clone()
         } else {
-            self.defocus_disk_sample()
+            self.defocus_disk_sample(lens)
         };
         let dir = pixel_sample - &orig;
-        let time = rng.gen();
+        let time = rng.get_1d();
+        let wavelengths = self.spectral.then(|| Wavelengths::sample(rng.get_1d()));
+
+        Ray {
+            wavelengths,
+            ..Ray::new(orig, dir, time)
+        }
+    }
+
+    fn defocus_disk_sample(&self, (u, v): (f64, f64)) -> Point3 {
+        let (x, y) = sampler::concentric_disk(u, v);
+        &self.center + x * &self.defocus_disk_u + y * &self.defocus_disk_v
+    }
+}
+
+/// The light a path carries: RGB, or in spectral mode a spectrum at the path's wavelengths.
+trait Radiance: Clone + Add<Output = Self> + Mul<Output = Self> + Div<f64, Output = Self> {
+    /// `color`, a reflectance or emission met along `r`, as this kind of light.
+    fn from_color(color: Color, r: &Ray) -> Self;
+
+    /// `self`, the light arriving along `scattered`, as it counts toward the path along `r`.
+    fn carry(self, _r: &Ray, _scattered: &Ray) -> Self {
+        self
+    }
+}
+
+impl Radiance for Color {
+    fn from_color(color: Color, _r: &Ray) -> Self {
+        color
+    }
+}
+
+impl Radiance for Spectrum {
+    fn from_color(color: Color, r: &Ray) -> Self {
+        Spectrum::from_rgb(
+            &color,
+            &r.wavelengths.expect("spectral paths have wavelengths"),
+        )
+    }
 
-        Ray::new(orig, dir, time)
+    fn carry(self, r: &Ray, scattered: &Ray) -> Self {
+        match (r.wavelengths, scattered.wavelengths) {
+            (Some(w), Some(next)) => w.dispersion(&next) * self,
+            _ => self,
+        }
+    }
+}
+
+/// Where the samples of one tile are traced and splatted, and the size of the stratified grids
+/// they are drawn from.
+struct Target<'a> {
+    film: FilmTile,
+    world: &'a Hittable,
+    lights: &'a Hittable,
+    grid: usize,
+}
+
+/// A render between passes over the image. Every pass refines the whole image, so the image
+/// after any pass is a valid, if noisier, result.
+pub struct Render<'a> {
+    camera: &'a Camera,
+    samples_per_pass: usize,
+    region: Tile,
+    tiles: Vec<Tile>,
+    film: Film,
+    pixels: Vec<PixelStats>,
+    passes: usize,
+    /// The samples in each stratified grid, kept from the start of the render.
+    grid: usize,
+    done: bool,
+    bar: ProgressBar,
+}
+
+impl Render<'_> {
+    /// Adds the next samples to every pixel that still needs them.
+    pub fn pass(&mut self, world: &Hittable, lights: &Hittable) {
+        let camera = self.camera;
+        let tiles = self.tiles.clone();
+        for batch in tiles.chunks(TILES_PER_BATCH) {
+            // each tile works on a copy of its pixels' statistics, written back as it is merged
+            let rendered: Vec<_> = batch
+                .par_iter()
+                .progress_with(self.bar.clone())
+                .map(|&t| {
+                    let mut stats = self.tile_stats(t);
+                    let film_tile = camera.render_tile(t, &mut stats, self.grid, world, lights);
+                    (film_tile, stats)
+                })
+                .collect();
+
+            for (&t, (film_tile, stats)) in batch.iter().zip(rendered) {
+                self.merge_tile(t, &film_tile, &stats);
+            }
+        }
+        self.end_pass();
+    }
+
+    /// A copy of the statistics of the pixels in `tile`, row by row, with the samples each takes
+    /// in this pass.
+    pub(crate) fn tile_stats(&self, tile: Tile) -> Vec<PixelStats> {
+        let width = self.camera.image_width as usize;
+        let mut stats = Vec::with_capacity((tile.width() * tile.height()) as usize);
+        for j in tile.y0..tile.y1 {
+            let row = j as usize * width;
+            stats.extend_from_slice(&self.pixels[row + tile.x0 as usize..row + tile.x1 as usize]);
+        }
+        stats
+    }
+
+    /// Adds the samples taken in `tile` this pass, splatted into `film_tile` and counted in
+    /// `stats`, to the render. Tiles must be merged in the order of `tiles` for the image to come
+    /// out the same however they were rendered.
+    pub(crate) fn merge_tile(&mut self, tile: Tile, film_tile: &FilmTile, stats: &[PixelStats]) {
+        let width = self.camera.image_width as usize;
+        self.film.merge(film_tile);
+        for (j, row) in (tile.y0..tile.y1).zip(stats.chunks(tile.width() as usize)) {
+            let start = j as usize * width + tile.x0 as usize;
+            for (pixel, stats) in self.pixels[start..start + row.len()].iter_mut().zip(row) {
+                // statistics sent back by a worker don't say whether the pixel is done
+                *pixel = PixelStats {
+                    done: self.camera.is_pixel_done(stats),
+                    ..stats.clone()
+                };
+            }
+        }
+    }
+
+    /// Finishes a pass once every tile is merged and schedules the samples of the next.
+    pub(crate) fn end_pass(&mut self) {
+        let camera = self.camera;
+        self.passes += 1;
+        self.done = !camera.schedule(
+            region_pixels_mut(&mut self.pixels, camera.image_width, self.region),
+            self.samples_per_pass,
+        );
+        if self.done {
+            self.bar.finish();
+        }
+    }
+
+    /// The tiles every pass goes over, in the order they are merged.
+    pub(crate) fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    /// The progress bar, which counts the tiles rendered in every pass.
+    pub(crate) fn progress(&self) -> &ProgressBar {
+        &self.bar
+    }
+
+    /// Whether every pixel in the region has all the samples it needs.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn passes(&self) -> usize {
+        self.passes
     }
 
-    fn pixel_sample_square(&self) -> Vector3 {
-        let mut rng = rand::thread_rng();
-        let px = -0.5 * rng.gen::<f64>();
-        let py = -0.5 * rng.gen::<f64>();
-        px * &self.pixel_delta_u + py * &self.pixel_delta_v
+    /// The image of the region from the samples taken so far.
+    pub fn image(&self) -> Framebuffer {
+        self.film.to_framebuffer(self.region)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = Vector3::random_in_unit_disk();
-        &self.center + p.x * &self.defocus_disk_u + p.y * &self.defocus_disk_v
+    /// The images of the region for each AOV the camera records.
+    pub fn aov_images(&self) -> Vec<(Aov, Framebuffer)> {
+        self.film.aov_framebuffers(self.region)
+    }
+
+    /// Saves the film sums and the samples taken in every pixel so `Camera::resume` can carry on
+    /// from here. Each sample's random numbers depend only on the seed, its pixel and its index,
+    /// so the counts are all the generator state there is. Like images, the state is written
+    /// beside `path` and renamed into place.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let partial = output::partial_path(path);
+        let result = (|| {
+            let mut writer = BufWriter::new(File::create(&partial)?);
+            self.camera
+                .state_header(self.passes as u64, self.grid as u64)
+                .write(&mut writer)?;
+            self.film.save(&mut writer)?;
+            for stats in &self.pixels {
+                writer.write_all(&(stats.samples as u64).to_le_bytes())?;
+                writer.write_all(&stats.mean.to_le_bytes())?;
+                writer.write_all(&stats.m2.to_le_bytes())?;
+            }
+            writer.flush()
+        })();
+        if let Err(e) = result {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+        fs::rename(&partial, path)
+    }
+}
+
+fn region_pixels(
+    pixels: &[PixelStats],
+    width: u32,
+    region: Tile,
+) -> impl Iterator<Item = &PixelStats> {
+    (region.y0..region.y1).flat_map(move |j| {
+        let row = (j * width) as usize;
+        &pixels[row + region.x0 as usize..row + region.x1 as usize]
+    })
+}
+
+fn region_pixels_mut(
+    pixels: &mut [PixelStats],
+    width: u32,
+    region: Tile,
+) -> impl Iterator<Item = &mut PixelStats> {
+    pixels
+        .chunks_mut(width as usize)
+        .skip(region.y0 as usize)
+        .take(region.height() as usize)
+        .flat_map(move |row| &mut row[region.x0 as usize..region.x1 as usize])
+}
+
+/// Identifies a saved render: everything besides the sample counts that must match to resume it.
+#[derive(Debug, PartialEq)]
+struct StateHeader {
+    width: u32,
+    height: u32,
+    seed: u64,
+    scene: u64,
+    view: u64,
+    settings: u64,
+    grid: u64,
+    passes: u64,
+}
+
+impl StateHeader {
+    const MAGIC: &'static [u8; 8] = b"RTSTATE2";
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(Self::MAGIC)?;
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        for v in [
+            self.seed,
+            self.scene,
+            self.view,
+            self.settings,
+            self.grid,
+            self.passes,
+        ] {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        if reader.read_exact(&mut magic).is_err() || &magic != Self::MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a saved render",
+            ));
+        }
+        Ok(StateHeader {
+            width: read_u32(reader)?,
+            height: read_u32(reader)?,
+            seed: read_u64(reader)?,
+            scene: read_u64(reader)?,
+            view: read_u64(reader)?,
+            settings: read_u64(reader)?,
+            grid: read_u64(reader)?,
+            passes: read_u64(reader)?,
+        })
+    }
+}
+
+/// The samples taken in one pixel so far, with Welford's running mean and sum of squared
+/// deviations of their luminance, and the samples it takes in the next pass.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PixelStats {
+    samples: usize,
+    mean: f64,
+    m2: f64,
+    done: bool,
+    batch: usize,
+}
+
+impl PixelStats {
+    /// Writes the statistics and the samples scheduled for the next pass for `read`.
+    pub(crate) fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.samples as u64).to_le_bytes())?;
+        writer.write_all(&self.mean.to_le_bytes())?;
+        writer.write_all(&self.m2.to_le_bytes())?;
+        writer.write_all(&(self.batch as u64).to_le_bytes())
+    }
+
+    /// Reads back what `write` wrote. Whether the pixel is done is left to `Render::merge_tile`.
+    pub(crate) fn read(reader: &mut impl Read) -> io::Result<Self> {
+        Ok(PixelStats {
+            samples: read_u64(reader)? as usize,
+            mean: read_f64(reader)?,
+            m2: read_f64(reader)?,
+            batch: read_u64(reader)? as usize,
+            done: false,
+        })
+    }
+
+    /// The samples the pixel takes in the next pass.
+    pub(crate) fn batch(&self) -> usize {
+        self.batch
+    }
+
+    fn add(&mut self, luminance: f64) {
+        self.samples += 1;
+        let delta = luminance - self.mean;
+        self.mean += delta / self.samples as f64;
+        self.m2 += delta * (luminance - self.mean);
+    }
+
+    /// The standard error of the mean luminance, relative to the mean.
+    fn error(&self) -> f64 {
+        let n = self.samples as f64;
+        let variance = self.m2 / (n - 1.0).max(1.0);
+        (variance / n).sqrt() / self.mean.abs().max(MIN_LUMINANCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        aov::Aov,
+        color::Color,
+        film::Film,
+        hittable::{ConstantMedium, Hittable, HittableList, Quad, Sphere},
+        material::{Dielectric, DiffuseLight, Lambertian, Metal},
+        point3::Point3,
+        sampler::Sampler,
+        texture::{Noise, SolidColor},
+        tile::{Tile, TileOrder},
+        vector3::Vector3,
+    };
+
+    use super::{Adaptive, Camera, PixelStats, Render, Target};
+
+    /// A small scene with glass, metal, a textured ground, fog and an area light, returned with
+    /// the light on its own.
+    fn scene() -> (Hittable, Hittable) {
+        let light = Quad::new(
+            Point3::new(-1.0, 3.0, -1.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+            Arc::new(DiffuseLight::new(Arc::new(SolidColor::new(Color::new(
+                8.0, 8.0, 8.0,
+            ))))),
+        );
+        let world = HittableList::new(vec![
+            Sphere::new(
+                Point3::new(0.0, -100.5, 0.0),
+                100.0,
+                Arc::new(Lambertian::new(Arc::new(Noise::new(4.0, 0)))),
+            ),
+            Sphere::new(
+                Point3::new(-0.6, 0.0, 0.0),
+                0.5,
+                Arc::new(Dielectric::new(1.5)),
+            ),
+            Sphere::new(
+                Point3::new(0.6, 0.0, 0.0),
+                0.5,
+                Arc::new(Metal::new_solid(Color::new(0.8, 0.6, 0.2), 0.3)),
+            ),
+            ConstantMedium::new(
+                Box::new(Sphere::new(
+                    Point3::new(0.0, 0.0, 1.0),
+                    0.4,
+                    Arc::new(Dielectric::new(1.5)),
+                )),
+                2.0,
+                Arc::new(SolidColor::new(Color::new(0.9, 0.9, 0.9))),
+            ),
+            light.clone(),
+        ]);
+        (world, light)
+    }
+
+    fn camera(samples_per_pixel: usize) -> Camera {
+        Camera::new(
+            16,
+            1.0,
+            Point3::new(0.0, 1.0, 4.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.5,
+            4.0,
+            samples_per_pixel,
+            8,
+            Color::new(0.1, 0.1, 0.1),
+        )
+    }
+
+    #[test]
+    fn test_render_is_deterministic() {
+        let (world, light) = scene();
+        let camera = camera(8).with_seed(7);
+
+        let render = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| camera.render(&world, &light))
+        };
+        let single = render(1);
+        assert_eq!(single.pixels(), render(4).pixels());
+
+        let reseeded = camera.with_seed(8).render(&world, &light);
+        assert_ne!(single.pixels(), reseeded.pixels());
+    }
+
+    #[test]
+    fn test_progressive_render() {
+        let (world, light) = scene();
+        let camera = camera(8);
+        let complete = camera.render(&world, &light);
+
+        // 8 passes of a single sample each
+        let mut render = camera.start(Some(1));
+        render.pass(&world, &light);
+        assert!(!render.is_done());
+        assert_ne!(render.image().pixels(), complete.pixels());
+        while !render.is_done() {
+            render.pass(&world, &light);
+        }
+        assert_eq!(render.passes(), 8);
+        // with the box filter each pixel adds up the same samples in the same order
+        assert_eq!(render.image().pixels(), complete.pixels());
+    }
+
+    #[test]
+    fn test_resume() {
+        let (world, light) = scene();
+        let run = |render: &mut Render| {
+            while !render.is_done() {
+                render.pass(&world, &light);
+            }
+        };
+        let path = std::env::temp_dir().join(format!("resume-{}.bin", std::process::id()));
+
+        let first = camera(3);
+        let mut render = first.start(Some(1));
+        run(&mut render);
+        render.save(&path).unwrap();
+
+        // carrying on to 8 samples matches a render that took all 8 from the start
+        let more = camera(8);
+        let mut resumed = more.resume(Some(1), &path).unwrap();
+        run(&mut resumed);
+        assert_eq!(resumed.passes(), 8);
+        let mut complete = more.start(Some(1));
+        run(&mut complete);
+        assert_eq!(resumed.image().pixels(), complete.image().pixels());
+
+        let error = camera(8).with_seed(1).resume(None, &path).err().unwrap();
+        assert_eq!(error.to_string(), "it was rendered with seed 0, not 1");
+        let error = camera(8)
+            .with_scene_key(1)
+            .resume(None, &path)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "it was rendered from a different scene");
+        let mut deeper = camera(8);
+        deeper.max_depth = 9;
+        let error = deeper.resume(None, &path).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "it was rendered with a different camera position, lens, depth or background"
+        );
+
+        // a stratified render keeps the grid it started with, so carrying on to 8 samples draws
+        // the last 5 from a second grid of 3 rather than restratifying on a grid of 8
+        let stratified = |spp| camera(spp).with_sampler(Sampler::Stratified);
+        let fewer = stratified(3);
+        let mut render = fewer.start(Some(1));
+        run(&mut render);
+        render.save(&path).unwrap();
+        let more = stratified(8);
+        let mut resumed = more.resume(Some(1), &path).unwrap();
+        run(&mut resumed);
+        let pixels = vec![PixelStats::default(); 16 * 16];
+        let mut complete = more.begin(Some(1), more.film(), pixels, 0, 3);
+        run(&mut complete);
+        assert_eq!(resumed.image().pixels(), complete.image().pixels());
+        let mut restratified = more.start(Some(1));
+        run(&mut restratified);
+        assert_ne!(resumed.image().pixels(), restratified.image().pixels());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_region() {
+        let (world, light) = scene();
+        let full = camera(8).render(&world, &light);
+
+        // with the box filter a pixel only sees its own samples, so a crop renders them exactly
+        let region = Tile::new(3, 5, 11, 9);
+        let cropped = camera(8)
+            .with_tiles(3, TileOrder::Hilbert)
+            .with_region(region)
+            .render(&world, &light);
+        assert_eq!((cropped.width, cropped.height), (8, 4));
+        for y in 0..4 {
+            for x in 0..8 {
+                assert_eq!(cropped.get(x, y), full.get(x + 3, y + 5));
+            }
+        }
+    }
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let (world, light) = scene();
+        let adaptive = Adaptive {
+            threshold: 0.02,
+            min_samples: 4,
+            max_samples: 256,
+        };
+        let generous = camera(64).with_adaptive(adaptive);
+        let samples = |i, j, world: &Hittable| {
+            let mut target = Target {
+                film: Film::new(16, 16, generous.filter).tile(Tile::new(0, 0, 16, 16)),
+                world,
+                lights: &light,
+                grid: 4,
+            };
+            let mut stats = [PixelStats::default()];
+            while generous.schedule(stats.iter_mut(), 1) {
+                generous.render_pixel(i, j, &mut stats[0], &mut target);
+            }
+            stats[0].samples
+        };
+
+        // the sky is flat, so it converges after the first batch
+        let empty = HittableList::new(vec![]);
+        assert_eq!(samples(0, 0, &empty), 4);
+        // the fogged glass sphere in the middle is noisy and gets more, up to its budget
+        let noisy = samples(8, 8, &world);
+        assert!(noisy > 4 && noisy <= 64);
+
+        // over the image the samples the sky doesn't need go to the noisiest pixels, and no more
+        // are taken than the budget of 8 per pixel
+        let camera = camera(8).with_adaptive(adaptive);
+        let mut render = camera.start(None);
+        while !render.is_done() {
+            render.pass(&world, &light);
+        }
+        let counts: Vec<_> = render.pixels.iter().map(|p| p.samples).collect();
+        assert!(counts.iter().sum::<usize>() <= 8 * 256);
+        assert_eq!(counts[0], 4);
+        assert!(counts.iter().any(|&n| n > 8));
+
+        let render = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| camera.render(&world, &light))
+        };
+        assert_eq!(render(1).pixels(), render(3).pixels());
+    }
+
+    #[test]
+    fn test_adaptive_schedule_spends_whole_budget() {
+        let adaptive = Adaptive {
+            threshold: 0.001,
+            min_samples: 4,
+            max_samples: 256,
+        };
+        // 2 samples of the budget of 130 per pixel are left: too few for the noisier pixel's
+        // batch, but enough for the other, which is 2 short of max_samples
+        let camera = camera(130).with_adaptive(adaptive);
+        let mut pixels = [
+            PixelStats {
+                samples: 4,
+                mean: 1.0,
+                m2: 4.0,
+                ..PixelStats::default()
+            },
+            PixelStats {
+                samples: 254,
+                mean: 1.0,
+                m2: 1.0,
+                ..PixelStats::default()
+            },
+        ];
+        assert!(camera.schedule(pixels.iter_mut(), 1));
+        assert_eq!(pixels[0].batch, 0);
+        assert_eq!(pixels[1].batch, 2);
+    }
+
+    #[test]
+    fn test_aovs() {
+        let red = Color::new(0.7, 0.1, 0.1);
+        let world = HittableList::new(vec![
+            Sphere::new(
+                Point3::new(0.0, -100.5, 0.0),
+                100.0,
+                Arc::new(Lambertian::new_solid(Color::new(0.5, 0.5, 0.5))),
+            )
+            .with_object(0),
+            Sphere::new(
+                Point3::new(-0.6, 0.0, 0.0),
+                0.5,
+                Arc::new(Lambertian::new_solid(red.clone())),
+            )
+            .with_object(1),
+            Sphere::new(
+                Point3::new(0.6, 0.0, 0.0),
+                0.5,
+                Arc::new(Lambertian::new_solid(red.clone())),
+            )
+            .with_object(2),
+        ]);
+        let (_, light) = scene();
+        let plain = camera(4);
+        let camera = camera(4).with_aovs(Aov::ALL.to_vec());
+        let mut render = camera.start(None);
+        render.pass(&world, &light);
+        let aovs = render.aov_images();
+        let aov = |aov: Aov, x, y| {
+            aovs.iter()
+                .find(|(a, _)| *a == aov)
+                .unwrap()
+                .1
+                .get(x, y)
+                .clone()
+        };
+
+        // the sky
+        assert_eq!(aov(Aov::Albedo, 0, 0), Color::new(0.1, 0.1, 0.1));
+        assert_eq!(aov(Aov::Depth, 0, 0), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(aov(Aov::ObjectId, 0, 0), Color::new(0.0, 0.0, 0.0));
+
+        // the middle of the left sphere, about sqrt(17) - 0.5 from the camera
+        assert_eq!(aov(Aov::Albedo, 5, 8), red);
+        assert!((aov(Aov::Depth, 5, 8).r - 3.6).abs() < 0.2);
+        assert!(aov(Aov::Normal, 5, 8).b > 0.5);
+
+        // the spheres are different objects with equal materials, the ground neither
+        assert_ne!(aov(Aov::ObjectId, 5, 8), aov(Aov::ObjectId, 10, 8));
+        assert_eq!(aov(Aov::MaterialId, 5, 8), aov(Aov::MaterialId, 10, 8));
+        assert_ne!(aov(Aov::MaterialId, 5, 8), aov(Aov::MaterialId, 8, 15));
+
+        // recording them leaves the image as it was
+        assert_eq!(
+            render.image().pixels(),
+            plain.render(&world, &light).pixels()
+        );
     }
 }
@@ -0,0 +1,409 @@
+use std::{fmt, path::Path};
+
+use raytracing_rs::{
+    output::Format,
+    scene::{Aov, ToneMap},
+    tile::Tile,
+};
+
+pub const USAGE: &str = "\
+usage: raytracing-rs <scene.json> [options]
+
+options:
+  -o, --output <file>   image to write (default: image.png)
+      --format <fmt>    png, jpeg, ppm, exr or pfm (default: from the output extension)
+      --spp <n>         samples per pixel, or the average per pixel with --adaptive
+      --adaptive <error>
+                        sample the noisiest pixels more, until the relative error of each is below
+                        error or the samples run out
+      --width <px>      image width; the height follows the scene's aspect ratio
+      --depth <n>       maximum bounce depth
+      --threads <n>     worker threads (default: one per core)
+      --seed <n>        seed for the random samples; the same seed gives the same image
+      --exposure <ev>   brighten the image by ev stops, or darken it if negative
+      --white-balance <kelvin>
+                        make light of this color temperature come out white (6504 is neutral)
+      --tone-map <op>   clip, reinhard, aces or agx, for the 8-bit formats (default: clip)
+      --aovs <list>     also write these images of the first hit, each beside the output as
+                        <name>.<aov>.<ext>: albedo, normal, depth, position, uv, object_id and
+                        material_id, separated by commas
+      --denoise         denoise the image, guided by its albedo and normal, before writing it
+      --spectral        trace each sample at four wavelengths instead of in RGB, so dispersive
+                        glass splits light
+      --check           validate the scene and report problems without rendering
+      --progressive     render in passes of one sample per pixel, rewriting the output as it
+                        improves (default: every 30 seconds); Ctrl-C stops after the current pass
+      --checkpoint-passes <n>
+                        with --progressive, rewrite the output every n passes
+      --checkpoint-seconds <s>
+                        with --progressive, rewrite the output every s seconds
+      --region <x0,y0,x1,y1>
+                        render only the pixels x0..x1 by y0..y1 and crop the image to them
+      --coordinator <addr>
+                        listen on addr (such as 127.0.0.1:7878) and split the image into tiles
+                        for the workers that connect
+      --worker <addr>   render tiles for the coordinator at addr; the scene comes from the
+                        coordinator, so none is given
+      --resume <file>   carry on from the render state saved in file, if it exists, and save
+                        the state there whenever the output is written; raise --spp to add samples
+  -h, --help            print this message";
+
+/// Command line options. Anything left as `None` keeps the value from the scene file.
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub scene: String,
+    pub output: String,
+    pub format: Option<Format>,
+    pub samples_per_pixel: Option<usize>,
+    pub adaptive: Option<f64>,
+    pub image_width: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub exposure: Option<f64>,
+    pub white_balance: Option<f64>,
+    pub tone_map: Option<ToneMap>,
+    pub aovs: Option<Vec<Aov>>,
+    pub denoise: bool,
+    pub spectral: bool,
+    pub check: bool,
+    pub progressive: bool,
+    pub checkpoint_passes: Option<usize>,
+    pub checkpoint_seconds: Option<u64>,
+    pub resume: Option<String>,
+    pub region: Option<Tile>,
+    pub coordinator: Option<String>,
+    pub worker: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Help => write!(f, "help requested"),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, Error> {
+    let mut args = args.into_iter();
+    let mut scene = None;
+    let mut options = Options {
+        scene: String::new(),
+        output: "image.png".to_owned(),
+        format: None,
+        samples_per_pixel: None,
+        adaptive: None,
+        image_width: None,
+        max_depth: None,
+        threads: None,
+        seed: None,
+        exposure: None,
+        white_balance: None,
+        tone_map: None,
+        aovs: None,
+        denoise: false,
+        spectral: false,
+        check: false,
+        progressive: false,
+        checkpoint_passes: None,
+        checkpoint_seconds: None,
+        resume: None,
+        region: None,
+        coordinator: None,
+        worker: None,
+    };
+
+    while let Some(arg) = args.next() {
+        // accept both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_owned(), Some(v.to_owned())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| Error::Invalid(format!("{} requires a value", flag)))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Err(Error::Help),
+            "-o" | "--output" => options.output = value()?,
+            "--format" => {
+                let name = value()?;
+                options.format =
+                    Some(Format::from_name(&name).ok_or_else(|| {
+                        Error::Invalid(format!("unknown output format '{}'", name))
+                    })?);
+            }
+            "--spp" => options.samples_per_pixel = Some(parse_positive(&flag, &value()?)?),
+            "--adaptive" => {
+                let v = value()?;
+                options.adaptive = Some(v.parse().ok().filter(|t| *t > 0.0).ok_or_else(|| {
+                    Error::Invalid(format!("--adaptive expects a positive error, got '{}'", v))
+                })?);
+            }
+            "--width" => options.image_width = Some(parse_positive(&flag, &value()?)?),
+            "--depth" => options.max_depth = Some(parse_positive(&flag, &value()?)?),
+            "--threads" => options.threads = Some(parse_positive(&flag, &value()?)?),
+            "--seed" => {
+                let v = value()?;
+                options.seed = Some(v.parse().map_err(|_| {
+                    Error::Invalid(format!("--seed expects an integer, got '{}'", v))
+                })?);
+            }
+            "--exposure" => {
+                let v = value()?;
+                options.exposure = Some(v.parse().map_err(|_| {
+                    Error::Invalid(format!("--exposure expects a number, got '{}'", v))
+                })?);
+            }
+            "--white-balance" => {
+                let v = value()?;
+                options.white_balance = Some(v.parse().map_err(|_| {
+                    Error::Invalid(format!(
+                        "--white-balance expects a temperature, got '{}'",
+                        v
+                    ))
+                })?);
+            }
+            "--tone-map" => {
+                let name = value()?;
+                options.tone_map = Some(
+                    ToneMap::from_name(&name)
+                        .ok_or_else(|| Error::Invalid(format!("unknown tone map '{}'", name)))?,
+                );
+            }
+            "--aovs" => options.aovs = Some(parse_aovs(&value()?)?),
+            "--denoise" => options.denoise = true,
+            "--spectral" => options.spectral = true,
+            "--check" => options.check = true,
+            "--progressive" => options.progressive = true,
+            "--resume" => options.resume = Some(value()?),
+            "--region" => options.region = Some(parse_region(&value()?)?),
+            "--coordinator" => options.coordinator = Some(value()?),
+            "--worker" => options.worker = Some(value()?),
+            "--checkpoint-passes" => {
+                options.checkpoint_passes = Some(parse_positive(&flag, &value()?)?)
+            }
+            "--checkpoint-seconds" => {
+                options.checkpoint_seconds = Some(parse_positive(&flag, &value()?)?)
+            }
+            f if f.starts_with('-') => {
+                return Err(Error::Invalid(format!("unknown option '{}'", f)))
+            }
+            _ if scene.is_none() => scene = Some(arg),
+            _ => return Err(Error::Invalid(format!("unexpected argument '{}'", arg))),
+        }
+    }
+
+    if options.worker.is_some() {
+        if scene.is_some() || options.coordinator.is_some() {
+            return Err(Error::Invalid(
+                "--worker takes its scene from the coordinator".to_owned(),
+            ));
+        }
+        return Ok(options);
+    }
+    if options.coordinator.is_some() && (options.progressive || options.resume.is_some()) {
+        return Err(Error::Invalid(
+            "--coordinator can't be combined with --progressive or --resume".to_owned(),
+        ));
+    }
+
+    options.scene = scene.ok_or_else(|| Error::Invalid("missing scene file".to_owned()))?;
+    // catch an output name the image can't be written under before rendering it
+    if options.format.is_none() {
+        if let Some(ext) = Path::new(&options.output).extension() {
+            if Format::from_path(&options.output).is_none() {
+                return Err(Error::Invalid(format!(
+                    "unknown output format '{}'; use --format or one of png, jpg, ppm, exr or pfm",
+                    ext.to_string_lossy()
+                )));
+            }
+        }
+    }
+    if !options.progressive
+        && (options.checkpoint_passes.is_some() || options.checkpoint_seconds.is_some())
+    {
+        return Err(Error::Invalid("checkpoints need --progressive".to_owned()));
+    }
+    Ok(options)
+}
+
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, Error> {
+    value
+        .split(',')
+        .map(|name| {
+            Aov::from_name(name.trim())
+                .ok_or_else(|| Error::Invalid(format!("unknown AOV '{}'", name)))
+        })
+        .collect()
+}
+
+fn parse_region(value: &str) -> Result<Tile, Error> {
+    let invalid = || {
+        Error::Invalid(format!(
+            "--region expects x0,y0,x1,y1 with x0 < x1 and y0 < y1, got '{}'",
+            value
+        ))
+    };
+    let bounds = value
+        .split(',')
+        .map(|v| v.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    match bounds[..] {
+        [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok(Tile::new(x0, y0, x1, y1)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(
+    flag: &str,
+    value: &str,
+) -> Result<T, Error> {
+    value
+        .parse::<T>()
+        .ok()
+        .filter(|v| *v > T::default())
+        .ok_or_else(|| {
+            Error::Invalid(format!(
+                "{} expects a positive integer, got '{}'",
+                flag, value
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use raytracing_rs::{
+        output::Format,
+        scene::{Aov, ToneMap},
+        tile::Tile,
+    };
+
+    use super::{parse, Error};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let o = parse(args(
+            "scene.json -o out.exr --spp 500 --width=1920 --threads 8 --format pfm --seed 7",
+        ))
+        .unwrap();
+        assert_eq!(o.scene, "scene.json");
+        assert_eq!(o.output, "out.exr");
+        assert_eq!(o.format, Some(Format::Pfm));
+        assert_eq!(o.samples_per_pixel, Some(500));
+        assert_eq!(o.image_width, Some(1920));
+        assert_eq!(o.threads, Some(8));
+        assert_eq!(o.seed, Some(7));
+        assert_eq!(o.max_depth, None);
+        assert_eq!(o.adaptive, None);
+        assert!(!o.check);
+
+        assert!(parse(args("scene.json --check")).unwrap().check);
+        assert_eq!(
+            parse(args("scene.json --adaptive 0.01")).unwrap().adaptive,
+            Some(0.01)
+        );
+
+        let o = parse(args("scene.json --progressive --checkpoint-passes 10")).unwrap();
+        assert!(o.progressive);
+        assert_eq!(o.checkpoint_passes, Some(10));
+        assert_eq!(o.checkpoint_seconds, None);
+        assert_eq!(o.resume, None);
+
+        let o = parse(args("scene.json --resume=state.bin --region 10,20,30,40")).unwrap();
+        assert_eq!(o.resume.as_deref(), Some("state.bin"));
+        assert_eq!(o.region, Some(Tile::new(10, 20, 30, 40)));
+
+        let o = parse(args("scene.json --aovs albedo,normal,object_id")).unwrap();
+        assert_eq!(o.aovs, Some(vec![Aov::Albedo, Aov::Normal, Aov::ObjectId]));
+        assert!(!o.denoise);
+        assert!(!o.spectral);
+
+        let o = parse(args(
+            "scene.json --exposure -1.5 --white-balance 3200 --tone-map agx",
+        ))
+        .unwrap();
+        assert_eq!(o.exposure, Some(-1.5));
+        assert_eq!(o.white_balance, Some(3200.0));
+        assert_eq!(o.tone_map, Some(ToneMap::Agx));
+        assert!(parse(args("scene.json --denoise")).unwrap().denoise);
+        assert!(parse(args("scene.json --spectral")).unwrap().spectral);
+
+        let o = parse(args("--worker 127.0.0.1:7878 --threads 2")).unwrap();
+        assert_eq!(o.worker.as_deref(), Some("127.0.0.1:7878"));
+        assert_eq!(o.scene, "");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(args("")),
+            Err(Error::Invalid("missing scene file".to_owned()))
+        );
+        assert_eq!(
+            parse(args("scene.json --spp")),
+            Err(Error::Invalid("--spp requires a value".to_owned()))
+        );
+        assert_eq!(
+            parse(args("scene.json --spp 0")),
+            Err(Error::Invalid(
+                "--spp expects a positive integer, got '0'".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse(args("scene.json --adaptive 0")),
+            Err(Error::Invalid(
+                "--adaptive expects a positive error, got '0'".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse(args("scene.json --bogus")),
+            Err(Error::Invalid("unknown option '--bogus'".to_owned()))
+        );
+        assert_eq!(
+            parse(args("scene.json --checkpoint-seconds 60")),
+            Err(Error::Invalid("checkpoints need --progressive".to_owned()))
+        );
+        assert_eq!(
+            parse(args("scene.json --region 5,0,5,10")),
+            Err(Error::Invalid(
+                "--region expects x0,y0,x1,y1 with x0 < x1 and y0 < y1, got '5,0,5,10'".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse(args("scene.json --worker 127.0.0.1:7878")),
+            Err(Error::Invalid(
+                "--worker takes its scene from the coordinator".to_owned()
+            ))
+        );
+        assert_eq!(
+            parse(args("scene.json --aovs albedo,shadow")),
+            Err(Error::Invalid("unknown AOV 'shadow'".to_owned()))
+        );
+        assert_eq!(
+            parse(args("scene.json -o render.tif")),
+            Err(Error::Invalid(
+                "unknown output format 'tif'; use --format or one of png, jpg, ppm, exr or pfm"
+                    .to_owned()
+            ))
+        );
+        assert!(parse(args("scene.json -o render.tif --format png")).is_ok());
+        assert!(parse(args("scene.json -o render")).is_ok());
+        assert_eq!(parse(args("scene.json -h")), Err(Error::Help));
+    }
+}
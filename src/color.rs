@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::interval::Interval;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct Color {
     pub r: f64,
     pub g: f64,
@@ -15,8 +15,7 @@ impl Color {
         Color { r, g, b }
     }
 
-    pub fn random() -> Color {
-        let mut rng = rand::thread_rng();
+    pub fn random(rng: &mut impl Rng) -> Color {
         Color {
             r: rng.gen(),
             g: rng.gen(),
@@ -24,8 +23,7 @@ impl Color {
         }
     }
 
-    pub fn random_range(r: std::ops::Range<f64>) -> Color {
-        let mut rng = rand::thread_rng();
+    pub fn random_range(r: std::ops::Range<f64>, rng: &mut impl Rng) -> Color {
         Color {
             r: rng.gen_range(r.clone()),
             g: rng.gen_range(r.clone()),
@@ -33,22 +31,23 @@ impl Color {
         }
     }
 
-    pub fn write(&self, samples_per_pixel: usize) -> String {
-        let scale = 1.0 / samples_per_pixel as f64;
+    /// Relative luminance with the Rec. 709 primaries.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 
-        let c = self * scale;
-        let r = linear_to_gamma(c.r);
-        let g = linear_to_gamma(c.g);
-        let b = linear_to_gamma(c.b);
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        let r = linear_to_gamma(self.r);
+        let g = linear_to_gamma(self.g);
+        let b = linear_to_gamma(self.b);
 
         let intensity = Interval::new(0.0, 0.999);
 
-        format!(
-            "{} {} {}",
-            (256.0 * intensity.clamp(r)).floor(),
-            (256.0 * intensity.clamp(g)).floor(),
-            (256.0 * intensity.clamp(b)).floor()
-        )
+        [
+            (256.0 * intensity.clamp(r)).floor() as u8,
+            (256.0 * intensity.clamp(g)).floor() as u8,
+            (256.0 * intensity.clamp(b)).floor() as u8,
+        ]
     }
 }
 
@@ -200,7 +199,44 @@ impl std::ops::Div<Color> for f64 {
     }
 }
 
+/// The sRGB transfer function, from linear light to the encoded value.
 #[inline(always)]
 fn linear_to_gamma(linear_component: f64) -> f64 {
-    linear_component.sqrt()
+    if linear_component <= 0.003_130_8 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A 3 by 3 matrix acting on colors, row by row.
+pub(crate) type Matrix3 = [[f64; 3]; 3];
+
+/// Linear sRGB, with its D65 white, to CIE XYZ.
+pub(crate) const SRGB_TO_XYZ: Matrix3 = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175_0],
+    [0.019_333_9, 0.119_192_0, 0.950_304_1],
+];
+
+/// CIE XYZ to linear sRGB.
+pub(crate) const XYZ_TO_SRGB: Matrix3 = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266_0, 1.876_010_8, 0.041_556_0],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+pub(crate) fn transform(m: &Matrix3, c: &Color) -> Color {
+    let row = |r: &[f64; 3]| r[0] * c.r + r[1] * c.g + r[2] * c.b;
+    Color::new(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+pub(crate) fn multiply(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
 }
@@ -0,0 +1,164 @@
+//! Denoising of low-sample renders.
+//!
+//! The filter is a joint non-local means: each pixel becomes a weighted average of the pixels
+//! around it, weighted by how alike the patches around the two are and by how alike their albedo
+//! and normal are. The albedo and normal AOVs have next to no noise, so they keep edges and
+//! texture that the noisy image alone can't tell from noise.
+
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{color::Color, framebuffer::Framebuffer};
+
+/// How far, in pixels, the pixels averaged into each pixel can be.
+const SEARCH_RADIUS: i64 = 7;
+
+/// The radius of the patches compared.
+const PATCH_RADIUS: i64 = 1;
+
+/// How different, relative to their brightness, two patches can be before they stop counting.
+const PATCH_STRENGTH: f64 = 0.35;
+
+/// The albedo difference at which a pixel's weight has fallen to 1/e.
+const ALBEDO_SIGMA: f64 = 0.05;
+
+/// The normal difference at which a pixel's weight has fallen to 1/e.
+const NORMAL_SIGMA: f64 = 0.2;
+
+/// Denoises `image` guided by its `albedo` and `normal` AOVs, which must be the same size.
+///
+/// The image is divided by the albedo first, so the filter only smooths the lighting and the
+/// texture is put back untouched afterwards.
+pub fn denoise(image: &Framebuffer, albedo: &Framebuffer, normal: &Framebuffer) -> Framebuffer {
+    let (width, height) = (image.width as i64, image.height as i64);
+    assert!(
+        albedo.width == image.width && albedo.height == image.height,
+        "the albedo is {}x{}, not {}x{}",
+        albedo.width,
+        albedo.height,
+        width,
+        height
+    );
+    assert!(
+        normal.width == image.width && normal.height == image.height,
+        "the normals are {}x{}, not {}x{}",
+        normal.width,
+        normal.height,
+        width,
+        height
+    );
+
+    // black surfaces and the background have no albedo to divide by
+    let divisor = |a: f64| if a > 0.01 { a } else { 1.0 };
+    let divisors: Vec<Color> = albedo
+        .pixels()
+        .iter()
+        .map(|a| Color::new(divisor(a.r), divisor(a.g), divisor(a.b)))
+        .collect();
+    let lighting: Vec<Color> = image
+        .pixels()
+        .iter()
+        .zip(&divisors)
+        .map(|(c, d)| Color::new(c.r / d.r, c.g / d.g, c.b / d.b))
+        .collect();
+
+    let at = |x: i64, y: i64| (y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize;
+    let patch_distance = |x0: i64, y0: i64, x1: i64, y1: i64| {
+        let mut sum = 0.0;
+        for dy in -PATCH_RADIUS..=PATCH_RADIUS {
+            for dx in -PATCH_RADIUS..=PATCH_RADIUS {
+                let p = &lighting[at(x0 + dx, y0 + dy)];
+                let q = &lighting[at(x1 + dx, y1 + dy)];
+                for (p, q) in [(p.r, q.r), (p.g, q.g), (p.b, q.b)] {
+                    sum += (p - q) * (p - q) / (0.01 + p * p + q * q);
+                }
+            }
+        }
+        sum / (3 * (2 * PATCH_RADIUS + 1) * (2 * PATCH_RADIUS + 1)) as f64
+    };
+
+    let pixels = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let i = at(x, y);
+            let (a, n) = (
+                albedo.get(x as u32, y as u32),
+                normal.get(x as u32, y as u32),
+            );
+
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+            let mut total = 0.0;
+            for qy in (y - SEARCH_RADIUS).max(0)..=(y + SEARCH_RADIUS).min(height - 1) {
+                for qx in (x - SEARCH_RADIUS).max(0)..=(x + SEARCH_RADIUS).min(width - 1) {
+                    let (qa, qn) = (
+                        albedo.get(qx as u32, qy as u32),
+                        normal.get(qx as u32, qy as u32),
+                    );
+                    let exponent = patch_distance(x, y, qx, qy) / (PATCH_STRENGTH * PATCH_STRENGTH)
+                        + distance_squared(a, qa) / (ALBEDO_SIGMA * ALBEDO_SIGMA)
+                        + distance_squared(n, qn) / (NORMAL_SIGMA * NORMAL_SIGMA);
+                    let weight = (-exponent).exp();
+                    sum += &(&lighting[at(qx, qy)] * weight);
+                    total += weight;
+                }
+            }
+
+            // the pixel itself always has weight 1, so total is never 0
+            let c = &sum / total;
+            let d = &divisors[i];
+            Color::new(c.r * d.r, c.g * d.g, c.b * d.b)
+        })
+        .collect();
+    Framebuffer::from_pixels(image.width, image.height, pixels)
+}
+
+fn distance_squared(a: &Color, b: &Color) -> f64 {
+    (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{color::Color, framebuffer::Framebuffer, random::SampleRng};
+
+    use super::denoise;
+
+    #[test]
+    fn test_denoise() {
+        // two flat surfaces with different albedo, lit the same and rendered with noise
+        let (width, height) = (32, 16);
+        let albedo_at = |x: u32| if x < 16 { 0.2 } else { 0.8 };
+        let mut rng = SampleRng::seed_from_u64(1);
+        let mut image = Framebuffer::new(width, height);
+        let mut albedo = Framebuffer::new(width, height);
+        let normal = Framebuffer::from_pixels(
+            width,
+            height,
+            vec![Color::new(0.0, 0.0, 1.0); (width * height) as usize],
+        );
+        for y in 0..height {
+            for x in 0..width {
+                let a = albedo_at(x);
+                let noise = rng.gen_range(0.5..1.5);
+                image.set(x, y, Color::new(a, a, a) * noise);
+                albedo.set(x, y, Color::new(a, a, a));
+            }
+        }
+
+        let error = |fb: &Framebuffer| {
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| (fb.get(x, y).r - albedo_at(x)).powi(2))
+                .sum::<f64>()
+        };
+        let denoised = denoise(&image, &albedo, &normal);
+        assert!(error(&denoised) < 0.2 * error(&image));
+
+        // the edge between the surfaces stays sharp
+        for y in 0..height {
+            assert!((denoised.get(15, y).r - 0.2).abs() < 0.1);
+            assert!((denoised.get(16, y).r - 0.8).abs() < 0.3);
+        }
+    }
+}
@@ -0,0 +1,473 @@
+//! Rendering one image across several processes.
+//!
+//! A coordinator hands the tiles of the image out to workers over TCP and merges the film tiles
+//! they send back. Workers receive the scene itself, so they only need the files it refers to,
+//! such as meshes and image textures, at the same relative paths.
+//!
+//! The coordinator renders in passes, as `Camera::render` does, and schedules the samples of
+//! every pass over the whole image, so adaptive sampling shares one budget between all tiles.
+//!
+//! Every message is little-endian. The coordinator opens with `MAGIC` and the scene as
+//! length-prefixed JSON, and the worker answers with how many tiles it renders at once. After
+//! that the coordinator sends `TILE` messages, each a tile index, its bounds and the statistics
+//! of its pixels with the samples each takes, and the worker answers each with the index, the
+//! `FilmTile` and the updated statistics. `DONE` ends the session.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use indicatif::ProgressBar;
+
+use crate::{
+    aov::Aov,
+    camera::{Camera, PixelStats},
+    film::{read_u32, read_u64, Film, FilmTile},
+    framebuffer::Framebuffer,
+    scene::Scene,
+    tile::Tile,
+};
+
+const MAGIC: &[u8; 8] = b"RTDIST02";
+const DONE: u8 = 0;
+const TILE: u8 = 1;
+
+/// The longest scene a worker accepts, so a corrupt length can't make it run out of memory.
+const MAX_SCENE_LEN: u64 = 256 << 20;
+
+/// How long a worker keeps trying to reach a coordinator that isn't listening yet.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an idle connection waits before checking for tiles again.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A rendered tile's samples and the statistics of its pixels after taking them.
+type TileResult = (FilmTile, Vec<PixelStats>);
+
+/// The tiles of the current pass still to hand out and the results that have come back.
+struct Work {
+    tiles: Vec<Tile>,
+    queue: Mutex<VecDeque<usize>>,
+    /// The statistics of each queued tile's pixels, with the samples each takes this pass.
+    stats: Mutex<Vec<Vec<PixelStats>>>,
+    results: Mutex<Vec<Option<TileResult>>>,
+    remaining: AtomicUsize,
+    finished: AtomicBool,
+}
+
+/// Renders `camera`'s image, and its AOVs, on the workers that connect to `listener`, sending
+/// each the scene `camera` was built from as JSON.
+///
+/// Each pass is scheduled and its tiles merged in the order `Camera::render` uses, so the image,
+/// adaptive or not, does not depend on how many workers there are or which tile each rendered.
+/// A worker that disconnects has its unfinished tiles handed to the others.
+pub fn coordinate(
+    listener: TcpListener,
+    scene: &str,
+    camera: &Camera,
+) -> io::Result<(Framebuffer, Vec<(Aov, Framebuffer)>)> {
+    let mut render = camera.start(None);
+    let tiles = render.tiles().to_vec();
+    let work = Work {
+        queue: Mutex::new(VecDeque::new()),
+        stats: Mutex::new(vec![Vec::new(); tiles.len()]),
+        results: Mutex::new((0..tiles.len()).map(|_| None).collect()),
+        remaining: AtomicUsize::new(0),
+        finished: AtomicBool::new(false),
+        tiles,
+    };
+    let bar = render.progress().clone();
+    let film = camera.film();
+
+    listener.set_nonblocking(true)?;
+    thread::scope(|s| {
+        let result = (|| {
+            while !render.is_done() {
+                // tiles with no samples to take this pass are left out
+                {
+                    let mut stats = work.stats.lock().unwrap();
+                    let mut queue = work.queue.lock().unwrap();
+                    for (index, &t) in work.tiles.iter().enumerate() {
+                        let tile_stats = render.tile_stats(t);
+                        if tile_stats.iter().any(|p| p.batch() > 0) {
+                            stats[index] = tile_stats;
+                            queue.push_back(index);
+                        } else {
+                            bar.inc(1);
+                        }
+                    }
+                    work.remaining.store(queue.len(), Ordering::SeqCst);
+                }
+
+                while work.remaining.load(Ordering::SeqCst) > 0 {
+                    match listener.accept() {
+                        Ok((stream, addr)) => {
+                            let (work, film, bar) = (&work, &film, &bar);
+                            s.spawn(move || {
+                                if let Err(e) = serve(stream, scene, work, film, bar) {
+                                    bar.suspend(|| eprintln!("worker {} dropped: {}", addr, e));
+                                }
+                            });
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(POLL_INTERVAL)
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                let mut results = work.results.lock().unwrap();
+                for (&t, result) in work.tiles.iter().zip(results.iter_mut()) {
+                    if let Some((film_tile, stats)) = result.take() {
+                        render.merge_tile(t, &film_tile, &stats);
+                    }
+                }
+                drop(results);
+                render.end_pass();
+            }
+            Ok(())
+        })();
+        // let the workers go, even if the render failed
+        work.finished.store(true, Ordering::SeqCst);
+        result
+    })?;
+
+    Ok((render.image(), render.aov_images()))
+}
+
+/// Feeds tiles to one worker, keeping as many in flight as it renders at once.
+fn serve(
+    stream: TcpStream,
+    scene: &str,
+    work: &Work,
+    film: &Film,
+    bar: &ProgressBar,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(scene.len() as u64).to_le_bytes())?;
+    writer.write_all(scene.as_bytes())?;
+    writer.flush()?;
+    let capacity = read_u32(&mut reader)?.max(1) as usize;
+
+    let mut in_flight = Vec::new();
+    let result = (|| loop {
+        while in_flight.len() < capacity {
+            let Some(index) = work.queue.lock().unwrap().pop_front() else {
+                break;
+            };
+            let t = work.tiles[index];
+            writer.write_all(&[TILE])?;
+            for v in [index as u32, t.x0, t.y0, t.x1, t.y1] {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+            let stats = work.stats.lock().unwrap()[index].clone();
+            for stats in &stats {
+                stats.write(&mut writer)?;
+            }
+            in_flight.push(index);
+        }
+        writer.flush()?;
+
+        if in_flight.is_empty() {
+            if work.finished.load(Ordering::SeqCst) {
+                writer.write_all(&[DONE])?;
+                return writer.flush();
+            }
+            // the next pass may not have started, or another worker may drop tiles back into
+            // the queue
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        let index = read_u32(&mut reader)? as usize;
+        if !in_flight.contains(&index) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("sent tile {} without being asked for it", index),
+            ));
+        }
+        let t = work.tiles[index];
+        let film_tile = film.read_tile(t, &mut reader)?;
+        let stats = (0..t.width() * t.height())
+            .map(|_| PixelStats::read(&mut reader))
+            .collect::<io::Result<_>>()?;
+        in_flight.retain(|&i| i != index);
+        work.results.lock().unwrap()[index] = Some((film_tile, stats));
+        work.remaining.fetch_sub(1, Ordering::SeqCst);
+        bar.inc(1);
+    })();
+
+    if result.is_err() {
+        work.queue.lock().unwrap().extend(in_flight);
+    }
+    result
+}
+
+/// Connects to the coordinator at `addr` and renders tiles for it, one per rayon thread at a
+/// time, until the image is finished.
+pub fn work(addr: &str) -> io::Result<()> {
+    let stream = connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data(format!(
+            "{} is not a render coordinator",
+            addr
+        )));
+    }
+    let len = read_u64(&mut reader)?;
+    if len > MAX_SCENE_LEN {
+        return Err(invalid_data(format!(
+            "the scene is {} bytes, more than the {} a worker accepts",
+            len, MAX_SCENE_LEN
+        )));
+    }
+    let mut scene = vec![0; len as usize];
+    reader.read_exact(&mut scene)?;
+    let scene: Scene = serde_json::from_slice(&scene).map_err(invalid_data)?;
+    let (world, lights, camera) = scene.build().map_err(invalid_data)?;
+    let camera: Camera = camera.into();
+
+    writer.write_all(&(rayon::current_num_threads() as u32).to_le_bytes())?;
+    writer.flush()?;
+
+    // tiles render on the rayon pool while this thread reads requests and another sends results
+    let (sender, results) = mpsc::channel::<(u32, FilmTile, Vec<PixelStats>)>();
+    let send = thread::spawn(move || -> io::Result<()> {
+        for (index, film_tile, stats) in results {
+            writer.write_all(&index.to_le_bytes())?;
+            film_tile.write(&mut writer)?;
+            for stats in &stats {
+                stats.write(&mut writer)?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    });
+
+    let image = Tile::new(0, 0, camera.image_width(), camera.image_height());
+    let scene = Arc::new((world, lights, camera));
+    let received = (|| loop {
+        let mut tag = [0];
+        reader.read_exact(&mut tag)?;
+        match tag[0] {
+            DONE => return Ok(()),
+            TILE => {
+                let index = read_u32(&mut reader)?;
+                let t = Tile::new(
+                    read_u32(&mut reader)?,
+                    read_u32(&mut reader)?,
+                    read_u32(&mut reader)?,
+                    read_u32(&mut reader)?,
+                );
+                if t.x0 >= t.x1 || t.y0 >= t.y1 || !image.contains(&t) {
+                    return Err(invalid_data(format!(
+                        "tile {} has bounds {:?} that aren't a tile of the {}x{} image",
+                        index, t, image.x1, image.y1
+                    )));
+                }
+                let mut stats = (0..t.width() * t.height())
+                    .map(|_| PixelStats::read(&mut reader))
+                    .collect::<io::Result<Vec<_>>>()?;
+                let (scene, sender) = (scene.clone(), sender.clone());
+                rayon::spawn(move || {
+                    let (world, lights, camera) = &*scene;
+                    let film_tile = camera.render_tile(t, &mut stats, camera.grid(), world, lights);
+                    // a failed send means the connection is gone, which the reader reports
+                    let _ = sender.send((index, film_tile, stats));
+                });
+            }
+            tag => return Err(invalid_data(format!("unknown message {}", tag))),
+        }
+    })();
+    drop(sender);
+
+    received?;
+    send.join().expect("the result sender panicked")
+}
+
+/// Connects to `addr`, retrying for a while so workers can be started before the coordinator.
+fn connect(addr: &str) -> io::Result<TcpStream> {
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                if start.elapsed() > CONNECT_TIMEOUT {
+                    return Err(e);
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use crate::{
+        camera,
+        color::Color,
+        film::read_u32,
+        point3::Point3,
+        scene::{self, Adaptive, Aov, Filter, Geometry, Material, Scene, Texture, TileOrder},
+        vector3::Vector3,
+    };
+
+    use super::{coordinate, work, MAGIC, TILE};
+
+    /// A small scene seen through `camera`, as JSON.
+    fn scene_json(camera: scene::Camera) -> String {
+        let light = Geometry::new_quad(
+            Point3::new(-1.0, 3.0, -1.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+            Material::new_diffuse_light(Texture::new_solid(Color::new(8.0, 8.0, 8.0))),
+        );
+        let world = vec![
+            Geometry::new_sphere(
+                Point3::new(0.0, -100.5, 0.0),
+                100.0,
+                Material::new_lambertian(Texture::new_noise(4.0)),
+            ),
+            Geometry::new_sphere(
+                Point3::new(0.0, 0.0, 0.0),
+                0.5,
+                Material::new_dielectric(1.5),
+            ),
+            light.clone(),
+        ];
+        serde_json::to_string(&Scene::new(world, vec![light], camera)).unwrap()
+    }
+
+    /// Renders a small scene through `camera` locally and on three workers and checks that the
+    /// images and AOVs are the same.
+    fn assert_matches_local(camera: scene::Camera) {
+        let json = scene_json(camera);
+        let (world, lights, camera) = serde_json::from_str::<Scene>(&json)
+            .unwrap()
+            .build()
+            .unwrap();
+        let camera: camera::Camera = camera.into();
+        let mut local = camera.start(None);
+        while !local.is_done() {
+            local.pass(&world, &lights);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (image, aovs) = thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| work(&addr).unwrap());
+            }
+            coordinate(listener, &json, &camera).unwrap()
+        });
+        assert_eq!(image.pixels(), local.image().pixels());
+        for ((aov, fb), (local_aov, local_fb)) in aovs.iter().zip(local.aov_images()) {
+            assert_eq!(*aov, local_aov);
+            assert_eq!(fb.pixels(), local_fb.pixels());
+        }
+        assert_eq!(aovs.len(), 2);
+    }
+
+    fn camera(samples_per_pixel: usize) -> scene::Camera {
+        scene::Camera::new(
+            24,
+            1.5,
+            Point3::new(0.0, 1.0, 4.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            4.0,
+            samples_per_pixel,
+            6,
+            Color::new(0.1, 0.1, 0.1),
+        )
+        // a wide filter makes neighbouring tiles overlap on the film
+        .with_filter(Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        })
+        .with_tiles(5, TileOrder::Hilbert)
+        .with_aovs(vec![Aov::Depth, Aov::MaterialId])
+    }
+
+    #[test]
+    fn test_distributed_render() {
+        assert_matches_local(camera(4));
+    }
+
+    #[test]
+    fn test_distributed_adaptive_render() {
+        // the flat sky's samples go to the noisy glass, whichever tiles they are in
+        assert_matches_local(camera(8).with_adaptive(Adaptive::new(0.05, 4, 64)));
+    }
+
+    #[test]
+    fn test_worker_rejects_bad_messages() {
+        // a worker for a coordinator that answers with `coordinator`
+        let run = |coordinator: &dyn Fn(&mut TcpStream)| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            thread::scope(|s| {
+                let worker = s.spawn(|| work(&addr));
+                let (mut stream, _) = listener.accept().unwrap();
+                coordinator(&mut stream);
+                worker.join().unwrap().err().unwrap()
+            })
+        };
+
+        let e = run(&|stream| {
+            stream.write_all(MAGIC).unwrap();
+            stream.write_all(&u64::MAX.to_le_bytes()).unwrap();
+        });
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let json = scene_json(camera(4));
+        let e = run(&|stream| {
+            stream.write_all(MAGIC).unwrap();
+            stream
+                .write_all(&(json.len() as u64).to_le_bytes())
+                .unwrap();
+            stream.write_all(json.as_bytes()).unwrap();
+            read_u32(stream).unwrap();
+            // x1 is left of x0
+            stream.write_all(&[TILE]).unwrap();
+            for v in [0u32, 5, 0, 2, 4] {
+                stream.write_all(&v.to_le_bytes()).unwrap();
+            }
+        });
+        assert_eq!(
+            e.to_string(),
+            "tile 0 has bounds Tile { x0: 5, y0: 0, x1: 2, y1: 4 } that aren't a tile of the 24x16 \
+             image"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::io::{self, Read, Write};
+
+use crate::{aov::Aov, color::Color, filter::Filter, framebuffer::Framebuffer, tile::Tile};
+
+/// The filter-weighted sum of radiance and the sum of weights of the samples near one pixel.
+#[derive(Clone, Debug, Default)]
+pub struct Pixel {
+    pub sum: Color,
+    pub weight: f64,
+}
+
+/// The least weight a pixel is averaged over. The negative lobes of the Mitchell and Lanczos
+/// filters can bring a pixel's weight close to zero, or below it, where dividing by it would blow
+/// up the noise in its sum.
+const MIN_WEIGHT: f64 = 1e-3;
+
+impl Pixel {
+    pub fn color(&self) -> Color {
+        if self.weight < MIN_WEIGHT {
+            Color::new(0.0, 0.0, 0.0)
+        } else {
+            &self.sum / self.weight
+        }
+    }
+}
+
+/// The image being rendered. Samples are splatted into every pixel whose filter covers them and
+/// each pixel is the weighted average of those samples.
+///
+/// Threads render into their own `FilmTile`s, which are merged back one at a time. Merging in a
+/// fixed order keeps the sums, and so the image, independent of how the work was scheduled.
+///
+/// The film also averages any AOVs asked for. Those aren't filtered: each sample only counts
+/// towards its own pixel.
+#[derive(Clone, Debug)]
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    filter: Filter,
+    pixels: Vec<Pixel>,
+    aovs: Vec<Aov>,
+    /// Every pixel's AOVs, in the order of `aovs`, one pixel after another.
+    aov_pixels: Vec<Pixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Self {
+        Film {
+            width,
+            height,
+            filter,
+            pixels: vec![Pixel::default(); (width * height) as usize],
+            aovs: Vec::new(),
+            aov_pixels: Vec::new(),
+        }
+    }
+
+    /// Records `aovs` as well as the image.
+    pub fn with_aovs(self, aovs: Vec<Aov>) -> Self {
+        Film {
+            aov_pixels: vec![Pixel::default(); self.pixels.len() * aovs.len()],
+            aovs,
+            ..self
+        }
+    }
+
+    /// A tile for the samples taken in the pixels of `tile`.
+    pub fn tile(&self, tile: Tile) -> FilmTile {
+        FilmTile::new(tile, self.width, self.height, self.filter, &self.aovs)
+    }
+
+    /// Reads a tile written by `FilmTile::write` for the samples taken in `tile`.
+    pub fn read_tile(&self, tile: Tile, reader: &mut impl Read) -> io::Result<FilmTile> {
+        let mut film_tile = self.tile(tile);
+        let mut bounds = [0; 4];
+        for b in &mut bounds {
+            *b = read_u32(reader)?;
+        }
+        if bounds != [film_tile.x0, film_tile.y0, film_tile.x1, film_tile.y1] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected the film tile for {:?}, got bounds {:?}",
+                    tile, bounds
+                ),
+            ));
+        }
+        read_pixels(&mut film_tile.pixels, reader)?;
+        read_pixels(&mut film_tile.aov_pixels, reader)?;
+        Ok(film_tile)
+    }
+
+    pub fn merge(&mut self, tile: &FilmTile) {
+        let width = tile.x1 - tile.x0;
+        let n = self.aovs.len();
+        for (i, p) in tile.pixels.iter().enumerate() {
+            let x = tile.x0 + i as u32 % width;
+            let y = tile.y0 + i as u32 / width;
+            let k = (y * self.width + x) as usize;
+            let pixel = &mut self.pixels[k];
+            pixel.sum += &p.sum;
+            pixel.weight += p.weight;
+
+            let aovs = &tile.aov_pixels[i * n..(i + 1) * n];
+            for (pixel, p) in self.aov_pixels[k * n..(k + 1) * n].iter_mut().zip(aovs) {
+                pixel.sum += &p.sum;
+                pixel.weight += p.weight;
+            }
+        }
+    }
+
+    /// Writes the raw sums and weights, as little-endian `f64`s, for `load` to read back.
+    pub fn save(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_pixels(&self.pixels, writer)?;
+        write_pixels(&self.aov_pixels, writer)
+    }
+
+    /// Reads back what `save` wrote from a film of the same size and AOVs.
+    pub fn load(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        read_pixels(&mut self.pixels, reader)?;
+        read_pixels(&mut self.aov_pixels, reader)
+    }
+
+    /// The image of `region` of the film.
+    pub fn to_framebuffer(&self, region: Tile) -> Framebuffer {
+        let pixels = (region.y0..region.y1)
+            .flat_map(|y| (region.x0..region.x1).map(move |x| (x, y)))
+            .map(|(x, y)| self.pixels[(y * self.width + x) as usize].color())
+            .collect();
+        Framebuffer::from_pixels(region.width(), region.height(), pixels)
+    }
+
+    /// The images of `region` for each of the AOVs recorded.
+    pub fn aov_framebuffers(&self, region: Tile) -> Vec<(Aov, Framebuffer)> {
+        let n = self.aovs.len();
+        self.aovs
+            .iter()
+            .enumerate()
+            .map(|(a, &aov)| {
+                let pixels = (region.y0..region.y1)
+                    .flat_map(|y| (region.x0..region.x1).map(move |x| (x, y)))
+                    .map(|(x, y)| self.aov_pixels[(y * self.width + x) as usize * n + a].color())
+                    .collect();
+                (
+                    aov,
+                    Framebuffer::from_pixels(region.width(), region.height(), pixels),
+                )
+            })
+            .collect()
+    }
+}
+
+/// A rectangle of the film that one thread splats samples into.
+pub struct FilmTile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    filter: Filter,
+    pixels: Vec<Pixel>,
+    aovs: Vec<Aov>,
+    aov_pixels: Vec<Pixel>,
+}
+
+impl FilmTile {
+    /// A tile for the samples taken in the pixels of `tile` on a `width` by `height` film. It
+    /// also covers the neighbouring pixels those samples can reach through the filter.
+    pub fn new(tile: Tile, width: u32, height: u32, filter: Filter, aovs: &[Aov]) -> Self {
+        let r = (filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let x0 = tile.x0.saturating_sub(r);
+        let y0 = tile.y0.saturating_sub(r);
+        let x1 = (tile.x1 + r).min(width);
+        let y1 = (tile.y1 + r).min(height);
+        let len = ((x1 - x0) * (y1 - y0)) as usize;
+        FilmTile {
+            x0,
+            y0,
+            x1,
+            y1,
+            filter,
+            pixels: vec![Pixel::default(); len],
+            aovs: aovs.to_vec(),
+            aov_pixels: vec![Pixel::default(); len * aovs.len()],
+        }
+    }
+
+    /// Writes the bounds and the raw sums and weights for `Film::read_tile`.
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        for b in [self.x0, self.y0, self.x1, self.y1] {
+            writer.write_all(&b.to_le_bytes())?;
+        }
+        write_pixels(&self.pixels, writer)?;
+        write_pixels(&self.aov_pixels, writer)
+    }
+
+    /// Adds a sample at continuous film position (`x`, `y`), where pixel (i, j) spans
+    /// `i..i + 1` by `j..j + 1`, to every pixel of the tile its filter reaches.
+    pub fn splat(&mut self, x: f64, y: f64, color: &Color) {
+        let radius = self.filter.radius();
+        // pixel k has its centre at k + 0.5
+        let first = |c: f64, min: u32| ((c - 0.5 - radius).ceil().max(min as f64)) as u32;
+        let last =
+            |c: f64, max: u32| ((c - 0.5 + radius).floor() + 1.0).clamp(0.0, max as f64) as u32;
+
+        let width = self.x1 - self.x0;
+        for py in first(y, self.y0)..last(y, self.y1) {
+            for px in first(x, self.x0)..last(x, self.x1) {
+                let weight = self
+                    .filter
+                    .evaluate(px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if weight != 0.0 {
+                    let pixel = &mut self.pixels[((py - self.y0) * width + px - self.x0) as usize];
+                    pixel.sum += &(color * weight);
+                    pixel.weight += weight;
+                }
+            }
+        }
+    }
+
+    /// Adds one sample's AOVs, with `value` giving each, to pixel (`i`, `j`).
+    pub fn add_aovs(&mut self, i: u32, j: u32, value: impl Fn(Aov) -> Color) {
+        let n = self.aovs.len();
+        let k = ((j - self.y0) * (self.x1 - self.x0) + i - self.x0) as usize;
+        for (&aov, pixel) in self
+            .aovs
+            .iter()
+            .zip(&mut self.aov_pixels[k * n..(k + 1) * n])
+        {
+            pixel.sum += &value(aov);
+            pixel.weight += 1.0;
+        }
+    }
+}
+
+fn write_pixels(pixels: &[Pixel], writer: &mut impl Write) -> io::Result<()> {
+    for p in pixels {
+        for v in [p.sum.r, p.sum.g, p.sum.b, p.weight] {
+            writer.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn read_pixels(pixels: &mut [Pixel], reader: &mut impl Read) -> io::Result<()> {
+    for p in pixels {
+        p.sum = Color::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        p.weight = read_f64(reader)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{color::Color, filter::Filter, random::SampleRng, tile::Tile};
+
+    use super::{Film, Pixel};
+
+    #[test]
+    fn test_film() {
+        let filter = Filter::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        };
+        let tiles = [
+            Tile::new(0, 0, 8, 8),
+            Tile::new(8, 0, 16, 8),
+            Tile::new(0, 8, 8, 16),
+            Tile::new(8, 8, 16, 16),
+        ];
+        let color = Color::new(0.25, 0.5, 1.0);
+        let render = |film: &Film, tile: Tile, seed: u64| {
+            let mut rng = SampleRng::seed_from_u64(seed);
+            let mut film_tile = film.tile(tile);
+            for _ in 0..16 * tile.width() * tile.height() {
+                let x = tile.x0 as f64 + rng.gen::<f64>() * tile.width() as f64;
+                let y = tile.y0 as f64 + rng.gen::<f64>() * tile.height() as f64;
+                film_tile.splat(x, y, &color);
+            }
+            film_tile
+        };
+
+        // rendering the tiles in any order gives the same image, as long as they're merged in one
+        let mut film = Film::new(16, 16, filter);
+        for (seed, &tile) in tiles.iter().enumerate() {
+            let film_tile = render(&film, tile, seed as u64);
+            film.merge(&film_tile);
+        }
+        let mut reversed = Film::new(16, 16, filter);
+        let mut film_tiles: Vec<_> = tiles
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(seed, &tile)| render(&reversed, tile, seed as u64))
+            .collect();
+        film_tiles.reverse();
+        for film_tile in &film_tiles {
+            reversed.merge(film_tile);
+        }
+        let region = Tile::new(0, 0, 16, 16);
+        let image = film.to_framebuffer(region);
+        assert_eq!(image.pixels(), reversed.to_framebuffer(region).pixels());
+
+        // a constant image stays constant whatever the filter's lobes do to the weights
+        for c in image.pixels() {
+            assert!((c.r - color.r).abs() < 1e-9, "{:?}", c);
+            assert!((c.g - color.g).abs() < 1e-9, "{:?}", c);
+            assert!((c.b - color.b).abs() < 1e-9, "{:?}", c);
+        }
+
+        // pixels whose weight is about zero or less come out black rather than blowing up
+        for weight in [0.0, 1e-9, -0.5] {
+            let pixel = Pixel {
+                sum: color.clone(),
+                weight,
+            };
+            assert_eq!(pixel.color(), Color::new(0.0, 0.0, 0.0));
+        }
+    }
+}
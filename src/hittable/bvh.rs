@@ -1,76 +1,399 @@
-use std::cmp::Ordering;
-
 use rand::Rng;
 
-use crate::{aabb::Aabb, interval::Interval, ray::Ray};
+use crate::{aabb::Aabb, interval::Interval, point3::Point3, ray::Ray, vector3::Vector3};
+
+use super::{HitRecord, Hittable, HittableList};
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_OBJECTS: usize = 4;
+/// Cost of visiting a node relative to intersecting one object.
+const TRAVERSAL_COST: f64 = 0.125;
+/// Entries in the traversal stack, which holds at most one node per level of the tree.
+const STACK_SIZE: usize = 64;
+/// Depth past which the SAH gives way to median splits. Those halve the objects at every level,
+/// so the tree stays within `STACK_SIZE` levels however lopsided the SAH splits above were.
+const MAX_SAH_DEPTH: usize = STACK_SIZE / 2;
 
-use super::{HitRecord, Hittable};
+/// How `BvhNode::new_with_split` divides the objects at each level of the tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BvhSplit {
+    /// Binned surface area heuristic over the object centroids.
+    #[default]
+    Sah,
+    /// Median object along the longest axis of the centroid bounds.
+    Median,
+}
 
+/// A bounding volume hierarchy stored as a flat array of nodes in depth-first order.
+///
+/// The first child of an interior node is the node right after it, so only the second child's
+/// index is stored. Leaves refer to a contiguous range of `objects`.
 #[derive(Clone)]
 pub struct BvhNode {
-    left: Box<Hittable>,
-    right: Box<Hittable>,
+    nodes: Vec<LinearNode>,
+    objects: Vec<Hittable>,
+}
+
+#[derive(Clone)]
+struct LinearNode {
     bbox: Aabb,
+    /// First object for a leaf, second child for an interior node.
+    offset: usize,
+    /// Number of objects in a leaf; zero for interior nodes.
+    count: usize,
+    /// Axis the children were split along.
+    axis: usize,
 }
 
 impl BvhNode {
-    pub fn new(src_objects: &Vec<Hittable>, start: usize, end: usize) -> Hittable {
-        let mut objects = src_objects.clone();
-        let mut rng = rand::thread_rng();
-        let axis: usize = rng.gen_range(0..=2);
-
-        let object_span = end - start;
-
-        let (left, right) = if object_span == 1 {
-            (objects[start].clone(), objects[start].clone())
-        } else if object_span == 2 {
-            if box_compare(&objects[start], &objects[start + 1], axis) == Ordering::Less {
-                (objects[start].clone(), objects[start + 1].clone())
-            } else {
-                (objects[start + 1].clone(), objects[start].clone())
-            }
-        } else {
-            objects[start..end].sort_unstable_by(|a, b| box_compare(a, b, axis));
-            let mid = start + object_span / 2;
-            (
-                BvhNode::new(&objects, start, mid),
-                BvhNode::new(&objects, mid, end),
-            )
-        };
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(objects: Vec<Hittable>) -> Hittable {
+        BvhNode::new_with_split(objects, BvhSplit::default())
+    }
 
-        let bbox = Aabb::new_from_aabbs(&left.bounding_box(), &right.bounding_box());
+    /// Builds a tree over `objects`. The build only reorders indices and is deterministic, so the
+    /// same input always yields the same tree. A single object is returned as is.
+    pub fn new_with_split(objects: Vec<Hittable>, split: BvhSplit) -> Hittable {
+        if objects.len() <= 1 {
+            return objects
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| HittableList::new(vec![]));
+        }
+
+        let bboxes: Vec<Aabb> = objects.iter().map(|o| o.bounding_box()).collect();
+        let centroids: Vec<Point3> = bboxes.iter().map(|b| b.centroid()).collect();
+        let mut indices: Vec<usize> = (0..objects.len()).collect();
+
+        let mut builder = Builder {
+            bboxes: &bboxes,
+            centroids: &centroids,
+            split,
+            objects: objects.into_iter().map(Some).collect(),
+            nodes: vec![],
+            ordered: vec![],
+        };
+        builder.build(&mut indices, 0);
 
         Hittable::BvhNode(BvhNode {
-            left: Box::new(left),
-            right: Box::new(right),
+            nodes: builder.nodes,
+            objects: builder.ordered,
+        })
+    }
+
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        BvhNode {
+            objects: self
+                .objects
+                .into_iter()
+                .map(|o| o.with_object(object_id))
+                .collect(),
+            ..self
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
+        let inv_dir = Vector3::new(1.0 / r.dir.x, 1.0 / r.dir.y, 1.0 / r.dir.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_t = ray_t.max;
+        let mut stack = [0usize; STACK_SIZE];
+        let mut top = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node
+                .bbox
+                .hit_inv_dir(&r.orig, &inv_dir, Interval::new(ray_t.min, closest_t))
+            {
+                if node.count > 0 {
+                    for object in &self.objects[node.offset..node.offset + node.count] {
+                        if let Some(rec) = object.hit(r, Interval::new(ray_t.min, closest_t), rng) {
+                            closest_t = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                } else if dir_is_neg[node.axis] {
+                    // visit the child nearer to the ray origin first
+                    stack[top] = current + 1;
+                    top += 1;
+                    current = node.offset;
+                    continue;
+                } else {
+                    stack[top] = node.offset;
+                    top += 1;
+                    current += 1;
+                    continue;
+                }
+            }
+
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            current = stack[top];
+        }
+
+        closest
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.nodes[0].bbox.clone()
+    }
+}
+
+struct Builder<'a> {
+    bboxes: &'a [Aabb],
+    centroids: &'a [Point3],
+    split: BvhSplit,
+    objects: Vec<Option<Hittable>>,
+    nodes: Vec<LinearNode>,
+    ordered: Vec<Hittable>,
+}
+
+impl Builder<'_> {
+    /// Appends the subtree over `indices`, whose root is `depth` levels down, to `nodes` and
+    /// returns the index of its root.
+    fn build(&mut self, indices: &mut [usize], depth: usize) -> usize {
+        debug_assert!(
+            depth < STACK_SIZE,
+            "the BVH is deeper than its traversal stack"
+        );
+        let bbox = indices.iter().fold(Aabb::new_empty(), |acc, &i| {
+            Aabb::new_from_aabbs(&acc, &self.bboxes[i])
+        });
+        let node = self.nodes.len();
+        self.nodes.push(LinearNode {
             bbox,
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        let split = match self.split {
+            BvhSplit::Sah if depth < MAX_SAH_DEPTH => {
+                self.partition_sah(indices, &self.nodes[node].bbox)
+            }
+            _ => self.partition_median(indices),
+        };
+
+        match split {
+            Some((mid, axis)) => {
+                let (l, r) = indices.split_at_mut(mid);
+                self.build(l, depth + 1);
+                let second = self.build(r, depth + 1);
+                self.nodes[node].offset = second;
+                self.nodes[node].axis = axis;
+            }
+            None => {
+                self.nodes[node].offset = self.ordered.len();
+                self.nodes[node].count = indices.len();
+                for &i in indices.iter() {
+                    self.ordered.push(self.objects[i].take().unwrap());
+                }
+            }
+        }
+
+        node
+    }
+
+    fn centroid_bounds(&self, indices: &[usize]) -> Aabb {
+        indices.iter().fold(Aabb::new_empty(), |acc, &i| {
+            Aabb::new_from_aabbs(
+                &acc,
+                &Aabb::new_from_points(&self.centroids[i], &self.centroids[i]),
+            )
         })
     }
 
-    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        if !self.bbox.hit(r, &ray_t) {
+    /// Reorders `indices` so the two children are `[..mid]` and `[mid..]`, returning `mid` and
+    /// the split axis, or `None` if the objects should stay together in a leaf.
+    fn partition_sah(&self, indices: &mut [usize], bbox: &Aabb) -> Option<(usize, usize)> {
+        let n = indices.len();
+        if n == 1 {
             return None;
         }
 
-        self.left
-            .hit(r, ray_t)
-            .and_then(|rec| {
-                self.right
-                    .hit(r, Interval::new(ray_t.min, rec.t))
-                    .or_else(|| Some(rec))
-            })
-            .or_else(|| self.right.hit(r, ray_t))
+        let bounds = self.centroid_bounds(indices);
+        let mut best: Option<(f64, usize, usize)> = None;
+
+        for axis in 0..3 {
+            let extent = bounds.axis(axis);
+            if extent.size() <= 0.0 {
+                continue;
+            }
+
+            let mut counts = [0usize; SAH_BINS];
+            let mut boxes: [Aabb; SAH_BINS] = std::array::from_fn(|_| Aabb::new_empty());
+            for &i in indices.iter() {
+                let b = bin(&self.centroids[i], axis, &extent);
+                counts[b] += 1;
+                boxes[b] = Aabb::new_from_aabbs(&boxes[b], &self.bboxes[i]);
+            }
+
+            // sweep from the right to get the cost of everything above each split plane
+            let mut right_area = [0.0; SAH_BINS];
+            let mut right_count = [0usize; SAH_BINS];
+            let mut acc = Aabb::new_empty();
+            let mut count = 0;
+            for b in (1..SAH_BINS).rev() {
+                acc = Aabb::new_from_aabbs(&acc, &boxes[b]);
+                count += counts[b];
+                right_area[b] = acc.surface_area();
+                right_count[b] = count;
+            }
+
+            let mut acc = Aabb::new_empty();
+            let mut count = 0;
+            for b in 0..SAH_BINS - 1 {
+                acc = Aabb::new_from_aabbs(&acc, &boxes[b]);
+                count += counts[b];
+                if count == 0 || right_count[b + 1] == 0 {
+                    continue;
+                }
+                let cost = acc.surface_area() * count as f64
+                    + right_area[b + 1] * right_count[b + 1] as f64;
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, b));
+                }
+            }
+        }
+
+        match best {
+            Some((cost, axis, split_bin)) => {
+                let area = bbox.surface_area();
+                let split_cost = if area > 0.0 {
+                    TRAVERSAL_COST + cost / area
+                } else {
+                    TRAVERSAL_COST
+                };
+                if n <= MAX_LEAF_OBJECTS && n as f64 <= split_cost {
+                    return None;
+                }
+
+                let extent = bounds.axis(axis);
+                let (mut left, right): (Vec<usize>, Vec<usize>) = indices
+                    .iter()
+                    .partition(|&&i| bin(&self.centroids[i], axis, &extent) <= split_bin);
+                let mid = left.len();
+                left.extend(right);
+                indices.copy_from_slice(&left);
+                Some((mid, axis))
+            }
+            // every centroid is in the same place, so no plane separates them
+            None if n <= MAX_LEAF_OBJECTS => None,
+            None => Some((n / 2, 0)),
+        }
     }
 
-    pub fn bounding_box(&self) -> Aabb {
-        self.bbox.clone()
+    fn partition_median(&self, indices: &mut [usize]) -> Option<(usize, usize)> {
+        if indices.len() <= MAX_LEAF_OBJECTS {
+            return None;
+        }
+
+        let bounds = self.centroid_bounds(indices);
+        let axis = (0..3)
+            .max_by(|&a, &b| bounds.axis(a).size().total_cmp(&bounds.axis(b).size()))
+            .unwrap();
+        let mid = indices.len() / 2;
+        indices.select_nth_unstable_by(mid, |&a, &b| {
+            (&self.centroids[a])[axis].total_cmp(&(&self.centroids[b])[axis])
+        });
+        Some((mid, axis))
     }
 }
 
-fn box_compare(a: &Hittable, b: &Hittable, axis: usize) -> Ordering {
-    if a.bounding_box().axis(axis).min < b.bounding_box().axis(axis).min {
-        Ordering::Less
-    } else {
-        Ordering::Greater
+fn bin(centroid: &Point3, axis: usize, extent: &Interval) -> usize {
+    let offset = (centroid[axis] - extent.min) / extent.size();
+    ((offset * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::SeedableRng;
+
+    use crate::{
+        color::Color,
+        hittable::{HittableList, Sphere},
+        interval::Interval,
+        material::Lambertian,
+        point3::Point3,
+        random::SampleRng,
+        ray::Ray,
+        vector3::Vector3,
+    };
+
+    use super::{BvhNode, BvhSplit};
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let material = Arc::new(Lambertian::new_solid(Color::new(0.5, 0.5, 0.5)));
+        let spheres: Vec<_> = (0..200)
+            .map(|i| {
+                let f = i as f64;
+                let center = Point3::new((f * 7.3) % 20.0, (f * 3.1) % 20.0, (f * 5.7) % 20.0);
+                Sphere::new(center, 0.5 + (f % 3.0) * 0.25, Arc::clone(&material))
+            })
+            .collect();
+
+        let list = HittableList::new(spheres.clone());
+        let sah = BvhNode::new_with_split(spheres.clone(), BvhSplit::Sah);
+        let median = BvhNode::new_with_split(spheres, BvhSplit::Median);
+
+        let mut rng = SampleRng::seed_from_u64(0);
+        for i in 0..500 {
+            let f = i as f64;
+            let r = Ray::new(
+                Point3::new(-10.0, 10.0, -10.0),
+                Vector3::new(1.0, (f * 0.37).sin(), 1.0 + (f * 0.11).cos()),
+                0.0,
+            );
+            let expected = list
+                .hit(&r, Interval::new(0.001, f64::MAX), &mut rng)
+                .map(|h| h.t);
+            for bvh in [&sah, &median] {
+                let actual = bvh
+                    .hit(&r, Interval::new(0.001, f64::MAX), &mut rng)
+                    .map(|h| h.t);
+                assert_eq!(expected, actual);
+            }
+        }
+    }
+
+    #[test]
+    fn test_lopsided_bvh_fits_traversal_stack() {
+        // each SAH split only peels the farthest sphere off spheres spaced like this
+        let material = Arc::new(Lambertian::new_solid(Color::new(0.5, 0.5, 0.5)));
+        let spheres: Vec<_> = (0..120)
+            .map(|i| {
+                let x = 8f64.powi(i);
+                Sphere::new(Point3::new(x, 0.0, 0.0), 0.25 * x, Arc::clone(&material))
+            })
+            .collect();
+
+        let list = HittableList::new(spheres.clone());
+        let bvh = BvhNode::new(spheres);
+
+        let mut rng = SampleRng::seed_from_u64(0);
+        for i in 0..120 {
+            let x = 8f64.powi(i);
+            let r = Ray::new(
+                Point3::new(x, 0.0, -10.0 * x),
+                Vector3::new(0.0, 0.0, 1.0),
+                0.0,
+            );
+            let expected = list
+                .hit(&r, Interval::new(0.001, f64::MAX), &mut rng)
+                .map(|h| h.t);
+            let actual = bvh
+                .hit(&r, Interval::new(0.001, f64::MAX), &mut rng)
+                .map(|h| h.t);
+            assert!(expected.is_some());
+            assert_eq!(expected, actual);
+        }
     }
 }
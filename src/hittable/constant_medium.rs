@@ -18,27 +18,33 @@ pub struct ConstantMedium {
     boundary: Box<Hittable>,
     neg_inv_density: f64,
     phase_func: Arc<Material>,
+    object_id: u32,
 }
 
 impl ConstantMedium {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(boundary: Box<Hittable>, d: f64, a: Arc<Texture>) -> Hittable {
         Hittable::ConstantMedium(ConstantMedium {
             boundary,
             neg_inv_density: (-1.0 / d),
             phase_func: Arc::new(Isotropic::new(a)),
+            object_id: 0,
         })
     }
 
-    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        ConstantMedium { object_id, ..self }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
         let enable_debug = false;
-        let mut rng = rand::thread_rng();
         let debugging = enable_debug && rng.gen::<f64>() < 0.00001;
 
         self.boundary
-            .hit(r, Interval::universe())
+            .hit(r, Interval::universe(), rng)
             .and_then(|mut rec1| {
                 self.boundary
-                    .hit(r, Interval::new(rec1.t + 0.001, f64::MAX))
+                    .hit(r, Interval::new(rec1.t + 0.001, f64::MAX), rng)
                     .and_then(|mut rec2| {
                         if debugging {
                             eprintln!("\nray_tmin={}, ray_tmax={}", rec1.t, rec2.t);
@@ -80,6 +86,8 @@ impl ConstantMedium {
                                     u: 0.0,
                                     v: 0.0,
                                     front_face: true,
+                                    tangent: Vector3::new(0.0, 0.0, 0.0),
+                                    object: self.object_id,
                                 })
                             }
                         }
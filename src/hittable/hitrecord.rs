@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use crate::{material::Material, point3::Point3, ray::Ray, vector3::Vector3};
+use crate::{material::Material, onb::Onb, point3::Point3, ray::Ray, vector3::Vector3};
 
 #[derive(Clone, Debug)]
 pub struct HitRecord {
@@ -11,6 +11,11 @@ pub struct HitRecord {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    /// The direction in which u increases, dp/du, or zero where the surface has none. It needn't
+    /// be a unit vector or perpendicular to the normal.
+    pub tangent: Vector3,
+    /// The ID of the object that was hit, given to it by `Hittable::with_object`.
+    pub object: u32,
 }
 
 impl HitRecord {
@@ -37,6 +42,22 @@ impl HitRecord {
             u,
             v,
             front_face,
+            tangent: Vector3::new(0.0, 0.0, 0.0),
+            object: 0,
         }
     }
+
+    pub fn with_tangent(self, tangent: Vector3) -> Self {
+        HitRecord { tangent, ..self }
+    }
+
+    pub fn with_object(self, object: u32) -> Self {
+        HitRecord { object, ..self }
+    }
+
+    /// The shading frame around the normal with its u axis along the tangent, so that
+    /// anisotropic materials follow the surface's parameterization rather than the world's axes.
+    pub fn frame(&self) -> Onb {
+        Onb::new_with_tangent(&self.normal, &self.tangent)
+    }
 }
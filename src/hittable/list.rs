@@ -11,6 +11,7 @@ pub struct HittableList {
 }
 
 impl HittableList {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(objects: Vec<Hittable>) -> Hittable {
         let bbox = objects
             .iter()
@@ -20,12 +21,23 @@ impl HittableList {
         Hittable::List(HittableList { objects, bbox })
     }
 
-    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        HittableList {
+            objects: self
+                .objects
+                .into_iter()
+                .map(|o| o.with_object(object_id))
+                .collect(),
+            ..self
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
         let mut temp_rec: Option<HitRecord> = None;
         let mut closest_so_far = ray_t.max;
 
         for object in &self.objects {
-            if let Some(rec) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+            if let Some(rec) = object.hit(r, Interval::new(ray_t.min, closest_so_far), rng) {
                 closest_so_far = rec.t;
                 temp_rec = Some(rec);
             }
@@ -45,8 +57,7 @@ impl HittableList {
             .sum()
     }
 
-    pub fn random(&self, o: &Point3) -> Vector3 {
-        let mut rng = rand::thread_rng();
-        return self.objects[rng.gen_range(0..self.objects.len())].random(o);
+    pub fn random(&self, o: &Point3, rng: &mut impl Rng) -> Vector3 {
+        self.objects[rng.gen_range(0..self.objects.len())].random(o, rng)
     }
 }
@@ -0,0 +1,226 @@
+//! Triangle meshes loaded from Wavefront OBJ files.
+//!
+//! Only geometry is read: `v`, `vt`, `vn` and `f` statements. Polygonal faces are fan
+//! triangulated and everything else (groups, smoothing, materials) is ignored.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    sync::Arc,
+};
+
+use crate::{material::Material, point3::Point3, vector3::Vector3};
+
+use super::{BvhNode, Hittable, Triangle};
+
+/// Loads the OBJ file at `path` as a BVH of its triangles.
+pub fn load_obj(path: &str, material: Arc<Material>) -> io::Result<Hittable> {
+    obj(BufReader::new(File::open(path)?), material)
+}
+
+/// Reads an OBJ file from `reader` as a BVH of its triangles.
+pub fn obj<R: BufRead>(reader: R, material: Arc<Material>) -> io::Result<Hittable> {
+    Ok(BvhNode::new(parse_obj(reader, material)?))
+}
+
+struct FaceVertex {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+fn parse_obj<R: BufRead>(reader: R, material: Arc<Material>) -> io::Result<Vec<Hittable>> {
+    let mut positions: Vec<Point3> = vec![];
+    let mut uvs: Vec<(f64, f64)> = vec![];
+    let mut normals: Vec<Vector3> = vec![];
+    let mut triangles: Vec<Hittable> = vec![];
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = n + 1;
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => {
+                let [x, y, z] = parse_floats(tokens, line_no)?;
+                positions.push(Point3::new(x, y, z));
+            }
+            Some("vt") => {
+                // v is optional and defaults to 0
+                let [u] = parse_floats(&mut tokens, line_no)?;
+                let v = match tokens.next() {
+                    Some(v) => v.parse().map_err(|_| invalid(line_no, "invalid number"))?,
+                    None => 0.0,
+                };
+                uvs.push((u, v));
+            }
+            Some("vn") => {
+                let [x, y, z] = parse_floats(tokens, line_no)?;
+                normals.push(Vector3::new(x, y, z));
+            }
+            Some("f") => {
+                let face = tokens
+                    .map(|t| parse_face_vertex(t, &positions, &uvs, &normals, line_no))
+                    .collect::<io::Result<Vec<_>>>()?;
+                if face.len() < 3 {
+                    return Err(invalid(line_no, "face needs at least 3 vertices"));
+                }
+                for i in 1..face.len() - 1 {
+                    let corners = [&face[0], &face[i], &face[i + 1]];
+                    let vertices = corners.map(|c| positions[c.v].clone());
+                    let vn = match corners.map(|c| c.vn) {
+                        [Some(a), Some(b), Some(c)] => {
+                            Some([normals[a].clone(), normals[b].clone(), normals[c].clone()])
+                        }
+                        _ => None,
+                    };
+                    let vt = match corners.map(|c| c.vt) {
+                        [Some(a), Some(b), Some(c)] => Some([uvs[a], uvs[b], uvs[c]]),
+                        _ => None,
+                    };
+                    triangles.push(Triangle::new_with_attributes(
+                        vertices,
+                        vn,
+                        vt,
+                        Arc::clone(&material),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+fn parse_floats<'a, const N: usize>(
+    mut tokens: impl Iterator<Item = &'a str>,
+    line_no: usize,
+) -> io::Result<[f64; N]> {
+    let mut out = [0.0; N];
+    for v in out.iter_mut() {
+        *v = tokens
+            .next()
+            .ok_or_else(|| invalid(line_no, "too few values"))?
+            .parse()
+            .map_err(|_| invalid(line_no, "invalid number"))?;
+    }
+    Ok(out)
+}
+
+fn parse_face_vertex(
+    token: &str,
+    positions: &[Point3],
+    uvs: &[(f64, f64)],
+    normals: &[Vector3],
+    line_no: usize,
+) -> io::Result<FaceVertex> {
+    let mut parts = token.split('/');
+    let v = resolve_index(parts.next(), positions.len(), line_no)?
+        .ok_or_else(|| invalid(line_no, "face vertex without a position"))?;
+    let vt = resolve_index(parts.next(), uvs.len(), line_no)?;
+    let vn = resolve_index(parts.next(), normals.len(), line_no)?;
+    Ok(FaceVertex { v, vt, vn })
+}
+
+/// Converts a 1-based (or negative, relative to the end) OBJ index into a 0-based one.
+fn resolve_index(part: Option<&str>, len: usize, line_no: usize) -> io::Result<Option<usize>> {
+    let part = match part {
+        Some(p) if !p.is_empty() => p,
+        _ => return Ok(None),
+    };
+    let i: i64 = part
+        .parse()
+        .map_err(|_| invalid(line_no, "invalid index"))?;
+    let resolved = if i > 0 { i - 1 } else { len as i64 + i };
+    if i == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(invalid(line_no, "index out of range"));
+    }
+    Ok(Some(resolved as usize))
+}
+
+fn invalid(line_no: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line_no, msg),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::SeedableRng;
+
+    use crate::{
+        color::Color, interval::Interval, material::Lambertian, point3::Point3, random::SampleRng,
+        ray::Ray, vector3::Vector3,
+    };
+
+    use super::parse_obj;
+
+    const QUAD: &str = "
+# unit square in the xy plane, split into two triangles by the loader
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/4/1
+";
+
+    #[test]
+    fn test_parse_obj() {
+        let material = Arc::new(Lambertian::new_solid(Color::new(0.5, 0.5, 0.5)));
+        let triangles = parse_obj(QUAD.as_bytes(), material).unwrap();
+        assert_eq!(triangles.len(), 2);
+
+        let r = Ray::new(
+            Point3::new(0.25, 0.75, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = triangles
+            .iter()
+            .find_map(|t| t.hit(&r, Interval::new(0.001, f64::MAX), &mut rng))
+            .unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.75).abs() < 1e-12);
+        assert_eq!(rec.normal, Vector3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn test_parse_obj_errors() {
+        let material = Arc::new(Lambertian::new_solid(Color::new(0.5, 0.5, 0.5)));
+        let err = parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n".as_bytes(), material.clone())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 3: index out of range");
+        assert!(parse_obj("v 0 0\n".as_bytes(), material).is_err());
+    }
+
+    #[test]
+    fn test_parse_obj_one_value_uv() {
+        let material = Arc::new(Lambertian::new_solid(Color::new(0.5, 0.5, 0.5)));
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5\nvt 1\nvt 0 1\nf 1/1 2/2 3/3\n";
+        let triangles = parse_obj(obj.as_bytes(), material).unwrap();
+
+        // the first two UVs are (0.5, 0) and (1, 0)
+        let r = Ray::new(
+            Point3::new(0.1, 0.1, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let mut rng = SampleRng::seed_from_u64(0);
+        let rec = triangles[0]
+            .hit(&r, Interval::new(0.001, f64::MAX), &mut rng)
+            .unwrap();
+        assert!((rec.u - 0.5).abs() < 1e-12 && (rec.v - 0.1).abs() < 1e-12);
+    }
+}
@@ -2,14 +2,25 @@ mod bvh;
 mod constant_medium;
 mod hitrecord;
 mod list;
+pub mod mesh;
 mod quad;
 mod sphere;
+mod transform;
+mod triangle;
+
+use rand::Rng;
 
 use crate::{aabb::Aabb, interval::Interval, point3::Point3, ray::Ray, vector3::Vector3};
 
 pub use self::{
-    bvh::BvhNode, constant_medium::ConstantMedium, hitrecord::HitRecord, list::HittableList,
-    quad::Quad, sphere::Sphere,
+    bvh::{BvhNode, BvhSplit},
+    constant_medium::ConstantMedium,
+    hitrecord::HitRecord,
+    list::HittableList,
+    quad::Quad,
+    sphere::Sphere,
+    transform::{SingularMatrix, Transform},
+    triangle::Triangle,
 };
 
 #[derive(Clone)]
@@ -31,23 +42,51 @@ pub struct RotateY {
 pub enum Hittable {
     Translate(Translate),
     RotateY(RotateY),
+    Transform(Transform),
     Sphere(Sphere),
     Quad(Quad),
+    Triangle(Triangle),
     ConstantMedium(ConstantMedium),
     List(HittableList),
     BvhNode(BvhNode),
 }
 
 impl Hittable {
-    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    /// `rng` is only drawn from by participating media.
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
         match self {
-            Hittable::Translate(t) => t.hit(r, ray_t),
-            Hittable::RotateY(rot) => rot.hit(r, ray_t),
+            Hittable::Translate(t) => t.hit(r, ray_t, rng),
+            Hittable::RotateY(rot) => rot.hit(r, ray_t, rng),
+            Hittable::Transform(t) => t.hit(r, ray_t, rng),
             Hittable::Sphere(s) => s.hit(r, ray_t),
             Hittable::Quad(q) => q.hit(r, ray_t),
-            Hittable::ConstantMedium(c) => c.hit(r, ray_t),
-            Hittable::List(l) => l.hit(r, ray_t),
-            Hittable::BvhNode(b) => b.hit(r, ray_t),
+            Hittable::Triangle(t) => t.hit(r, ray_t),
+            Hittable::ConstantMedium(c) => c.hit(r, ray_t, rng),
+            Hittable::List(l) => l.hit(r, ray_t, rng),
+            Hittable::BvhNode(b) => b.hit(r, ray_t, rng),
+        }
+    }
+
+    /// Gives every primitive of this object `object_id` as the ID its hits report, for the
+    /// object ID AOV. A `Transform` reports it for everything it places instead, since what it
+    /// places may be shared with other transforms that need IDs of their own.
+    pub fn with_object(self, object_id: u32) -> Hittable {
+        match self {
+            Hittable::Translate(t) => Hittable::Translate(Translate {
+                object: Box::new(t.object.with_object(object_id)),
+                ..t
+            }),
+            Hittable::RotateY(r) => Hittable::RotateY(RotateY {
+                object: Box::new(r.object.with_object(object_id)),
+                ..r
+            }),
+            Hittable::Transform(t) => Hittable::Transform(t.with_object(object_id)),
+            Hittable::Sphere(s) => Hittable::Sphere(s.with_object(object_id)),
+            Hittable::Quad(q) => Hittable::Quad(q.with_object(object_id)),
+            Hittable::Triangle(t) => Hittable::Triangle(t.with_object(object_id)),
+            Hittable::ConstantMedium(c) => Hittable::ConstantMedium(c.with_object(object_id)),
+            Hittable::List(l) => Hittable::List(l.with_object(object_id)),
+            Hittable::BvhNode(b) => Hittable::BvhNode(b.with_object(object_id)),
         }
     }
 
@@ -55,8 +94,10 @@ impl Hittable {
         match self {
             Hittable::Translate(t) => t.bounding_box(),
             Hittable::RotateY(r) => r.bounding_box(),
+            Hittable::Transform(t) => t.bounding_box(),
             Hittable::Sphere(s) => s.bounding_box(),
             Hittable::Quad(q) => q.bounding_box(),
+            Hittable::Triangle(t) => t.bounding_box(),
             Hittable::ConstantMedium(c) => c.bounding_box(),
             Hittable::List(l) => l.bounding_box(),
             Hittable::BvhNode(b) => b.bounding_box(),
@@ -65,24 +106,33 @@ impl Hittable {
 
     pub fn pdf_value(&self, o: &Point3, v: &Vector3) -> f64 {
         match self {
+            Hittable::Translate(t) => t.pdf_value(o, v),
+            Hittable::RotateY(r) => r.pdf_value(o, v),
+            Hittable::Transform(t) => t.pdf_value(o, v),
             Hittable::Sphere(s) => s.pdf_value(o, v),
             Hittable::Quad(q) => q.pdf_value(o, v),
+            Hittable::Triangle(t) => t.pdf_value(o, v),
             Hittable::List(l) => l.pdf_value(o, v),
             _ => 0.0,
         }
     }
 
-    pub fn random(&self, orig: &Point3) -> Vector3 {
+    pub fn random(&self, orig: &Point3, rng: &mut impl Rng) -> Vector3 {
         match self {
-            Hittable::Sphere(s) => s.random(orig),
-            Hittable::Quad(q) => q.random(orig),
-            Hittable::List(l) => l.random(orig),
+            Hittable::Translate(t) => t.random(orig, rng),
+            Hittable::RotateY(r) => r.random(orig, rng),
+            Hittable::Transform(t) => t.random(orig, rng),
+            Hittable::Sphere(s) => s.random(orig, rng),
+            Hittable::Quad(q) => q.random(orig, rng),
+            Hittable::Triangle(t) => t.random(orig, rng),
+            Hittable::List(l) => l.random(orig, rng),
             _ => Vector3::new(1.0, 0.0, 0.0),
         }
     }
 }
 
 impl Translate {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(object: Box<Hittable>, offset: Vector3) -> Hittable {
         let bbox = &object.bounding_box() + &offset;
         Hittable::Translate(Translate {
@@ -92,21 +142,30 @@ impl Translate {
         })
     }
 
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
         let offset_r = Ray::new(&r.orig - &self.offset, r.dir.clone(), r.time);
 
-        self.object.hit(&offset_r, ray_t).and_then(|mut rec| {
+        self.object.hit(&offset_r, ray_t, rng).map(|mut rec| {
             rec.p += &self.offset;
-            Some(rec)
+            rec
         })
     }
 
     fn bounding_box(&self) -> Aabb {
         self.bbox.clone()
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vector3) -> f64 {
+        self.object.pdf_value(&(o - &self.offset), v)
+    }
+
+    fn random(&self, orig: &Point3, rng: &mut impl Rng) -> Vector3 {
+        self.object.random(&(orig - &self.offset), rng)
+    }
 }
 
 impl RotateY {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(object: Box<Hittable>, angle: f64) -> Hittable {
         let radians = angle.to_radians();
         let sin_theta = radians.sin();
@@ -140,35 +199,63 @@ impl RotateY {
         })
     }
 
-    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let origin = Point3::new(
-            self.cos_theta * r.orig.x - self.sin_theta * r.orig.z,
-            r.orig.y,
-            self.sin_theta * r.orig.x + self.cos_theta * r.orig.z,
-        );
-        let dir = Vector3::new(
-            self.cos_theta * r.dir.x - self.sin_theta * r.dir.z,
-            r.dir.y,
-            self.sin_theta * r.dir.x + self.cos_theta * r.dir.z,
+    /// The world coordinates `x`, `z` in the object's frame.
+    fn to_object(&self, x: f64, z: f64) -> (f64, f64) {
+        (
+            self.cos_theta * x - self.sin_theta * z,
+            self.sin_theta * x + self.cos_theta * z,
+        )
+    }
+
+    /// The object coordinates `x`, `z` in the world frame.
+    fn to_world(&self, x: f64, z: f64) -> (f64, f64) {
+        (
+            self.cos_theta * x + self.sin_theta * z,
+            -self.sin_theta * x + self.cos_theta * z,
+        )
+    }
+
+    fn point_to_object(&self, p: &Point3) -> Point3 {
+        let (x, z) = self.to_object(p.x, p.z);
+        Point3::new(x, p.y, z)
+    }
+
+    fn vector_to_object(&self, v: &Vector3) -> Vector3 {
+        let (x, z) = self.to_object(v.x, v.z);
+        Vector3::new(x, v.y, z)
+    }
+
+    fn vector_to_world(&self, v: &Vector3) -> Vector3 {
+        let (x, z) = self.to_world(v.x, v.z);
+        Vector3::new(x, v.y, z)
+    }
+
+    fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
+        let rotated_r = Ray::new(
+            self.point_to_object(&r.orig),
+            self.vector_to_object(&r.dir),
+            r.time,
         );
-        let rotated_r = Ray::new(origin, dir, r.time);
-
-        self.object.hit(&rotated_r, ray_t).and_then(|mut rec| {
-            rec.p = Point3::new(
-                self.cos_theta * rec.p.x + self.sin_theta * rec.p.z,
-                rec.p.y,
-                -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z,
-            );
-            rec.normal = Vector3::new(
-                self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z,
-                rec.normal.y,
-                -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z,
-            );
-            Some(rec)
+
+        self.object.hit(&rotated_r, ray_t, rng).map(|mut rec| {
+            let (x, z) = self.to_world(rec.p.x, rec.p.z);
+            rec.p = Point3::new(x, rec.p.y, z);
+            rec.normal = self.vector_to_world(&rec.normal);
+            rec.tangent = self.vector_to_world(&rec.tangent);
+            rec
         })
     }
 
     fn bounding_box(&self) -> Aabb {
         self.bbox.clone()
     }
+
+    fn pdf_value(&self, o: &Point3, v: &Vector3) -> f64 {
+        self.object
+            .pdf_value(&self.point_to_object(o), &self.vector_to_object(v))
+    }
+
+    fn random(&self, orig: &Point3, rng: &mut impl Rng) -> Vector3 {
+        self.vector_to_world(&self.object.random(&self.point_to_object(orig), rng))
+    }
 }
@@ -14,6 +14,7 @@ pub struct Quad {
     u: Vector3,
     v: Vector3,
     material: Arc<Material>,
+    object_id: u32,
     bbox: Aabb,
     normal: Vector3,
     d: f64,
@@ -22,6 +23,7 @@ pub struct Quad {
 }
 
 impl Quad {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(q: Point3, u: Vector3, v: Vector3, material: Arc<Material>) -> Hittable {
         let n = u.cross(&v);
         let normal = n.unit_vector();
@@ -33,6 +35,7 @@ impl Quad {
             u,
             v,
             material,
+            object_id: 0,
             bbox,
             normal,
             d,
@@ -91,6 +94,10 @@ impl Quad {
         HittableList::new(sides)
     }
 
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        Quad { object_id, ..self }
+    }
+
     pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
         let denom = self.normal.dot(&r.dir);
 
@@ -108,8 +115,8 @@ impl Quad {
         let alpha = self.w.dot(&planar_hitpoint.cross(&self.v));
         let beta = self.w.dot(&self.u.cross(&planar_hitpoint));
 
-        self.is_interior(alpha, beta).and_then(|(u, v)| {
-            Some(HitRecord::new(
+        self.is_interior(alpha, beta).map(|(u, v)| {
+            HitRecord::new(
                 r,
                 &self.normal,
                 intersection,
@@ -117,7 +124,9 @@ impl Quad {
                 u,
                 v,
                 Arc::clone(&self.material),
-            ))
+            )
+            .with_tangent(self.u.clone())
+            .with_object(self.object_id)
         })
     }
 
@@ -130,22 +139,21 @@ impl Quad {
             &Ray::new(o.clone(), v.clone(), 0.0),
             Interval::new(0.001, f64::MAX),
         )
-        .and_then(|rec| {
+        .map(|rec| {
             let distance_squared = rec.t * rec.t * v.length_squared();
             let cos = (v.dot(&rec.normal) / v.length()).abs();
-            Some(distance_squared / (cos * self.area))
+            distance_squared / (cos * self.area)
         })
         .unwrap_or(0.0)
     }
 
-    pub fn random(&self, orig: &Point3) -> Vector3 {
-        let mut rng = rand::thread_rng();
+    pub fn random(&self, orig: &Point3, rng: &mut impl Rng) -> Vector3 {
         let p = &self.q + (rng.gen::<f64>() * &self.u) + (rng.gen::<f64>() * &self.v);
         p - orig
     }
 
     fn is_interior(&self, a: f64, b: f64) -> Option<(f64, f64)> {
-        if (a < 0.0) || (1.0 < a) || (b < 0.0) || (1.0 < b) {
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
             None
         } else {
             Some((a, b))
@@ -14,17 +14,20 @@ pub struct Sphere {
     center1: Point3,
     radius: f64,
     material: Arc<Material>,
+    object_id: u32,
     center_vec: Option<Vector3>,
     bbox: Aabb,
 }
 
 impl Sphere {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(center: Point3, radius: f64, material: Arc<Material>) -> Hittable {
         let rvec = Vector3::new(radius, radius, radius);
         Hittable::Sphere(Sphere {
             center1: center.clone(),
             radius,
             material,
+            object_id: 0,
             center_vec: None,
             bbox: Aabb::new_from_points(&(&center - &rvec), &(center + rvec)),
         })
@@ -42,6 +45,7 @@ impl Sphere {
             center1: center1.clone(),
             radius,
             material,
+            object_id: 0,
             center_vec: Some(center_vec),
             bbox: Aabb::new_from_aabbs(
                 &Aabb::new_from_points(&(&center1 - &rvec), &(&center1 + &rvec)),
@@ -50,11 +54,12 @@ impl Sphere {
         })
     }
 
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        Sphere { object_id, ..self }
+    }
+
     pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let center = self
-            .center_vec
-            .as_ref()
-            .and_then(|v| Some(&self.center1 + r.time * v));
+        let center = self.center_vec.as_ref().map(|v| &self.center1 + r.time * v);
         let center = center.as_ref().unwrap_or(&self.center1);
         let oc = &r.orig - center;
         let a = r.dir.length_squared();
@@ -78,15 +83,21 @@ impl Sphere {
         let p = r.at(root);
         let outward_normal = (&p - center) / self.radius;
         let (u, v) = get_sphere_uv(&(&outward_normal).into());
-        Some(HitRecord::new(
-            r,
-            &outward_normal,
-            p,
-            root,
-            u,
-            v,
-            Arc::clone(&self.material),
-        ))
+        // u runs around the y axis
+        let tangent = Vector3::new(outward_normal.z, 0.0, -outward_normal.x);
+        Some(
+            HitRecord::new(
+                r,
+                &outward_normal,
+                p,
+                root,
+                u,
+                v,
+                Arc::clone(&self.material),
+            )
+            .with_tangent(tangent)
+            .with_object(self.object_id),
+        )
     }
 
     pub fn bounding_box(&self) -> Aabb {
@@ -98,32 +109,31 @@ impl Sphere {
             &Ray::new(o.clone(), v.clone(), 0.0),
             Interval::new(0.001, f64::MAX),
         )
-        .and_then(|_| {
+        .map(|_| {
             let r_sq = self.radius * self.radius;
             let c_o_sq = (&self.center1 - o).length_squared();
             if r_sq > c_o_sq {
-                Some(0.0)
+                0.0
             } else {
                 let cos_theta_max =
                     (1.0 - self.radius * self.radius / (&self.center1 - o).length_squared()).sqrt();
                 let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
-                Some(1.0 / solid_angle)
+                1.0 / solid_angle
             }
         })
         .unwrap_or(0.0)
     }
 
-    pub fn random(&self, o: &Point3) -> Vector3 {
+    pub fn random(&self, o: &Point3, rng: &mut impl Rng) -> Vector3 {
         let dir = &self.center1 - o;
         let distance_squared = dir.length_squared();
         let uvw = Onb::new(&dir);
-        uvw.local(&random_to_sphere(self.radius, distance_squared))
+        uvw.local(&random_to_sphere(self.radius, distance_squared, rng))
     }
 }
 
-fn random_to_sphere(radius: f64, distance_squared: f64) -> Vector3 {
-    let mut rng = rand::thread_rng();
+fn random_to_sphere(radius: f64, distance_squared: f64, rng: &mut impl Rng) -> Vector3 {
     let r1: f64 = rng.gen();
     let r2: f64 = rng.gen();
     let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
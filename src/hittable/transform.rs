@@ -0,0 +1,173 @@
+use std::{fmt, sync::Arc};
+
+use rand::Rng;
+
+use crate::{
+    aabb::Aabb, interval::Interval, matrix::Matrix4, point3::Point3, ray::Ray, vector3::Vector3,
+};
+
+use super::{HitRecord, Hittable};
+
+/// Places `object` in the world through an arbitrary invertible affine matrix. The object is
+/// shared, so many transforms of one mesh or BVH cost a matrix each rather than a copy.
+#[derive(Clone)]
+pub struct Transform {
+    object: Arc<Hittable>,
+    matrix: Matrix4,
+    inverse: Matrix4,
+    normal_matrix: Matrix4,
+    /// The determinant of `inverse`, for the change in solid angle between the two spaces.
+    inverse_determinant: f64,
+    bbox: Aabb,
+    /// The ID hits report instead of the object's own, so that every placement of a shared
+    /// object is told apart.
+    object_id: Option<u32>,
+}
+
+/// A transform matrix with no inverse, such as a scale with a zero component.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SingularMatrix;
+
+impl fmt::Display for SingularMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the transform matrix is singular")
+    }
+}
+
+impl std::error::Error for SingularMatrix {}
+
+impl Transform {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(object: Arc<Hittable>, matrix: Matrix4) -> Result<Hittable, SingularMatrix> {
+        let inverse = matrix.inverse().ok_or(SingularMatrix)?;
+        let normal_matrix = inverse.transpose();
+        let bbox = matrix.transform_aabb(&object.bounding_box()).pad();
+        Ok(Hittable::Transform(Transform {
+            object,
+            inverse_determinant: inverse.determinant(),
+            matrix,
+            inverse,
+            normal_matrix,
+            bbox,
+            object_id: None,
+        }))
+    }
+
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        Transform {
+            object_id: Some(object_id),
+            ..self
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
+        // the direction is left unnormalized so t means the same thing in both spaces
+        let local_r = Ray::new(
+            self.inverse.transform_point(&r.orig),
+            self.inverse.transform_vector(&r.dir),
+            r.time,
+        );
+
+        self.object.hit(&local_r, ray_t, rng).map(|mut rec| {
+            rec.p = self.matrix.transform_point(&rec.p);
+            rec.normal = self
+                .normal_matrix
+                .transform_vector(&rec.normal)
+                .unit_vector();
+            rec.tangent = self.matrix.transform_vector(&rec.tangent);
+            rec.object = self.object_id.unwrap_or(rec.object);
+            rec
+        })
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.bbox.clone()
+    }
+
+    pub fn pdf_value(&self, o: &Point3, v: &Vector3) -> f64 {
+        let local_v = self.inverse.transform_vector(&v.unit_vector());
+        let pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(o), &local_v);
+        // the inverse maps the unit direction v to local_v, and the solid angle around it by
+        // |det| / |local_v|³
+        pdf * self.inverse_determinant.abs() / local_v.length().powi(3)
+    }
+
+    pub fn random(&self, orig: &Point3, rng: &mut impl Rng) -> Vector3 {
+        let local = self.object.random(&self.inverse.transform_point(orig), rng);
+        self.matrix.transform_vector(&local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f64::consts::PI, sync::Arc};
+
+    use rand::SeedableRng;
+
+    use crate::{
+        color::Color,
+        hittable::{Hittable, Quad, RotateY, Translate},
+        material::Lambertian,
+        matrix::Matrix4,
+        point3::Point3,
+        random::SampleRng,
+        vector3::Vector3,
+    };
+
+    use super::Transform;
+
+    #[test]
+    fn test_transformed_light_pdf() {
+        let quad = || {
+            Quad::new(
+                Point3::new(-1.0, -1.0, 0.0),
+                Vector3::new(2.0, 0.0, 0.0),
+                Vector3::new(0.0, 2.0, 0.0),
+                Arc::new(Lambertian::new_solid(Color::new(1.0, 1.0, 1.0))),
+            )
+        };
+        let matrix = Matrix4::translation(&Vector3::new(0.0, 0.0, -1.0))
+            * Matrix4::rotation(&Vector3::new(1.0, 1.0, 0.0), 30.0)
+            * Matrix4::scale(&Vector3::new(2.0, 0.5, 1.0));
+        let lights = [
+            Transform::new(Arc::new(quad()), matrix).unwrap(),
+            Translate::new(Box::new(quad()), Vector3::new(0.5, 0.0, 0.0)),
+            RotateY::new(Box::new(quad()), 30.0),
+        ];
+        let o = Point3::new(0.2, 0.1, 1.5);
+        let mut rng = SampleRng::seed_from_u64(4);
+
+        for light in &lights {
+            // the pdf integrates to 1 over the sphere of directions
+            let n = 200_000;
+            let integral = (0..n)
+                .map(|_| light.pdf_value(&o, &Vector3::random_unit_vector(&mut rng)) * 4.0 * PI)
+                .sum::<f64>()
+                / n as f64;
+            let name = match light {
+                Hittable::Transform(_) => "Transform",
+                Hittable::Translate(_) => "Translate",
+                _ => "RotateY",
+            };
+            assert!(
+                (integral - 1.0).abs() < 0.03,
+                "{} integrates to {}",
+                name,
+                integral
+            );
+
+            // and sampled directions hit the light
+            for _ in 0..100 {
+                assert!(light.pdf_value(&o, &light.random(&o, &mut rng)) > 0.0);
+            }
+        }
+
+        assert!(Transform::new(
+            Arc::new(quad()),
+            Matrix4::scale(&Vector3::new(1.0, 0.0, 1.0))
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{
+    aabb::Aabb, interval::Interval, material::Material, point3::Point3, ray::Ray, vector3::Vector3,
+};
+
+use super::{HitRecord, Hittable};
+
+#[derive(Clone)]
+pub struct Triangle {
+    v0: Point3,
+    e1: Vector3,
+    e2: Vector3,
+    normals: Option<[Vector3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    material: Arc<Material>,
+    object_id: u32,
+    bbox: Aabb,
+    normal: Vector3,
+    /// dp/du, from the UVs if there are any.
+    tangent: Vector3,
+    area: f64,
+}
+
+impl Triangle {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<Material>) -> Hittable {
+        Triangle::new_with_attributes([v0, v1, v2], None, None, material)
+    }
+
+    /// Builds a triangle with optional per-vertex shading normals and texture coordinates. Without
+    /// UVs the hit record carries the barycentric coordinates of the hit point instead.
+    pub fn new_with_attributes(
+        vertices: [Point3; 3],
+        normals: Option<[Vector3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: Arc<Material>,
+    ) -> Hittable {
+        let [v0, v1, v2] = vertices;
+        let e1 = &v1 - &v0;
+        let e2 = &v2 - &v0;
+        let n = e1.cross(&e2);
+        // without UVs u is the first barycentric coordinate, which runs along e1
+        let tangent = uvs
+            .and_then(|[uv0, uv1, uv2]| {
+                let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+                let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+                let det = du1 * dv2 - dv1 * du2;
+                (det.abs() > 1e-12).then(|| (dv2 * &e1 - dv1 * &e2) / det)
+            })
+            .unwrap_or(e1.clone());
+        let bbox = Aabb::new_from_aabbs(
+            &Aabb::new_from_points(&v0, &v1),
+            &Aabb::new_from_points(&v0, &v2),
+        )
+        .pad();
+        Hittable::Triangle(Triangle {
+            v0,
+            e1,
+            e2,
+            normals: normals.map(|ns| ns.map(|n| n.unit_vector())),
+            uvs,
+            material,
+            object_id: 0,
+            bbox,
+            normal: n.unit_vector(),
+            tangent,
+            area: 0.5 * n.length(),
+        })
+    }
+
+    pub(super) fn with_object(self, object_id: u32) -> Self {
+        Triangle { object_id, ..self }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        // Möller–Trumbore
+        let pvec = r.dir.cross(&self.e2);
+        let det = self.e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = &r.orig - &self.v0;
+        let b1 = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&self.e1);
+        let b2 = r.dir.dot(&qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = self.e2.dot(&qvec) * inv_det;
+        if !ray_t.contains(t) {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let (u, v) = self.uvs.map_or((b1, b2), |[uv0, uv1, uv2]| {
+            (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            )
+        });
+
+        let outward_normal = self
+            .normals
+            .as_ref()
+            .map_or(self.normal.clone(), |[n0, n1, n2]| {
+                let n = (b0 * n0 + b1 * n1 + b2 * n2).unit_vector();
+                // keep shading normals on the geometric side so front_face stays correct
+                if n.dot(&self.normal) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            });
+
+        Some(
+            HitRecord::new(
+                r,
+                &outward_normal,
+                r.at(t),
+                t,
+                u,
+                v,
+                Arc::clone(&self.material),
+            )
+            .with_tangent(self.tangent.clone())
+            .with_object(self.object_id),
+        )
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.bbox.clone()
+    }
+
+    pub fn pdf_value(&self, o: &Point3, v: &Vector3) -> f64 {
+        self.hit(
+            &Ray::new(o.clone(), v.clone(), 0.0),
+            Interval::new(0.001, f64::MAX),
+        )
+        .map(|rec| {
+            let distance_squared = rec.t * rec.t * v.length_squared();
+            let cos = (v.dot(&self.normal) / v.length()).abs();
+            distance_squared / (cos * self.area)
+        })
+        .unwrap_or(0.0)
+    }
+
+    pub fn random(&self, orig: &Point3, rng: &mut impl Rng) -> Vector3 {
+        let mut a: f64 = rng.gen();
+        let mut b: f64 = rng.gen();
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+        let p = &self.v0 + (a * &self.e1) + (b * &self.e2);
+        p - orig
+    }
+}
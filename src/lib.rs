@@ -1,14 +1,28 @@
 pub mod aabb;
+pub mod aov;
 pub mod camera;
 pub mod color;
+pub mod denoise;
+pub mod distributed;
+pub mod film;
+pub mod filter;
+pub mod framebuffer;
 pub mod hittable;
 pub mod interval;
 pub mod material;
+pub mod matrix;
+pub mod microfacet;
 pub mod onb;
+pub mod output;
 pub mod pdf;
 pub mod perlin;
 pub mod point3;
+pub mod random;
 pub mod ray;
+pub mod sampler;
 pub mod scene;
+pub mod spectrum;
 pub mod texture;
+pub mod tile;
+pub mod tonemap;
 pub mod vector3;
@@ -1,6 +1,26 @@
-use std::env::{self};
-
-use raytracing_rs::scene;
+mod cli;
+
+use std::{
+    env::{self},
+    net::TcpListener,
+    path::Path,
+    process::{self, ExitCode},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use raytracing_rs::{
+    aov::Aov,
+    camera::{self, Render},
+    denoise, distributed,
+    framebuffer::Framebuffer,
+    hittable::Hittable,
+    output, scene,
+    tonemap::ToneMap,
+};
 
 // fn random_spheres(image_width: u32, samples_per_pixel: usize) -> (Hittable, Hittable, Camera) {
 //     let mut world: Vec<Hittable> = vec![];
@@ -474,10 +494,263 @@ use raytracing_rs::scene;
 //     (HittableList::new(world), light, cam)
 // }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let scene_file: &str = args.get(1).unwrap();
-    let (world, lights, camera) = scene::load(scene_file);
+/// Reports every problem `scene::validate` finds, one per line, without building the scene.
+fn check(filename: &str) -> ExitCode {
+    let scene = match scene::read(filename) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let diagnostics = scene::validate(&scene);
+    for d in &diagnostics {
+        println!("{}: {}", filename, d);
+    }
+    if diagnostics.is_empty() {
+        println!("{}: ok", filename);
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Writes the image to the output through `tone_map`, denoised if asked, and the image of each
+/// AOV in `written` beside it in the same format.
+fn write_images(
+    image: &Framebuffer,
+    aovs: &[(Aov, Framebuffer)],
+    written: &[Aov],
+    tone_map: &ToneMap,
+    options: &cli::Options,
+) -> Result<(), String> {
+    let find = |aov| aovs.iter().find(|(a, _)| *a == aov).map(|(_, fb)| fb);
+    let denoised = match (options.denoise, find(Aov::Albedo), find(Aov::Normal)) {
+        (true, Some(albedo), Some(normal)) => Some(denoise::denoise(image, albedo, normal)),
+        _ => None,
+    };
+    output::write(
+        denoised.as_ref().unwrap_or(image),
+        &options.output,
+        options.format,
+        tone_map,
+    )
+    .map_err(|e| format!("failed to write {}: {}", options.output, e))?;
+    for (aov, fb) in aovs.iter().filter(|(aov, _)| written.contains(aov)) {
+        let path = output::aov_path(&options.output, aov.name());
+        // the AOVs are data, not pictures of the scene
+        output::write(fb, &path, options.format, &ToneMap::default())
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Renders the image and writes it out. With `--progressive` the render runs in passes of one
+/// sample per pixel and the output is rewritten at every checkpoint, so a killed render still
+/// leaves the latest image behind. With `--resume` the render carries on from the saved state,
+/// if there is one, and the state is saved alongside every image written.
+fn render(
+    camera: &camera::Camera,
+    world: &Hittable,
+    lights: &Hittable,
+    written: &[Aov],
+    options: &cli::Options,
+) -> Result<(), String> {
+    let samples_per_pass = options.progressive.then_some(1);
+    let mut render = match &options.resume {
+        Some(state) if Path::new(state).exists() => camera
+            .resume(samples_per_pass, state)
+            .map_err(|e| format!("failed to resume from {}: {}", state, e))?,
+        _ => camera.start(samples_per_pass),
+    };
+    let checkpoint = |render: &Render| {
+        write_images(
+            &render.image(),
+            &render.aov_images(),
+            written,
+            camera.tone_map(),
+            options,
+        )?;
+        match &options.resume {
+            Some(state) => render
+                .save(state)
+                .map_err(|e| format!("failed to save {}: {}", state, e)),
+            None => Ok(()),
+        }
+    };
+
+    if !options.progressive {
+        while !render.is_done() {
+            render.pass(world, lights);
+        }
+        return checkpoint(&render);
+    }
+
+    // the first Ctrl-C finishes the current pass and writes the image; a second one quits at once
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = stop.clone();
+    ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    })
+    .expect("failed to install the Ctrl-C handler");
+
+    let every_seconds = match (options.checkpoint_passes, options.checkpoint_seconds) {
+        (None, None) => Some(30),
+        (_, seconds) => seconds,
+    };
+
+    let mut last_checkpoint = Instant::now();
+    while !render.is_done() && !stop.load(Ordering::SeqCst) {
+        render.pass(world, lights);
+
+        let due = options
+            .checkpoint_passes
+            .is_some_and(|n| render.passes() % n == 0)
+            || every_seconds.is_some_and(|s| last_checkpoint.elapsed() >= Duration::from_secs(s));
+        if due && !render.is_done() {
+            checkpoint(&render)?;
+            last_checkpoint = Instant::now();
+        }
+    }
+    if !render.is_done() {
+        eprintln!("stopped after {} passes", render.passes());
+    }
+
+    checkpoint(&render)
+}
+
+/// Renders on the workers that connect to `addr` and writes the image.
+fn coordinate(
+    addr: &str,
+    scene: &str,
+    camera: &camera::Camera,
+    written: &[Aov],
+    options: &cli::Options,
+) -> Result<(), String> {
+    let listener =
+        TcpListener::bind(addr).map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+    if let Ok(local) = listener.local_addr() {
+        eprintln!("waiting for workers on {}", local);
+    }
+
+    let (image, aovs) = distributed::coordinate(listener, scene, camera)
+        .map_err(|e| format!("distributed render failed: {}", e))?;
+    write_images(&image, &aovs, written, camera.tone_map(), options)
+}
+
+fn main() -> ExitCode {
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(cli::Error::Help) => {
+            println!("{}", cli::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if options.check {
+        return check(&options.scene);
+    }
+
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("thread pool already initialized");
+    }
+
+    if let Some(addr) = &options.worker {
+        return match distributed::work(addr) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let scene = match scene::read(&options.scene) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut camera = scene.camera().clone();
+    if let Some(width) = options.image_width {
+        camera = camera.with_image_width(width);
+    }
+    if let Some(spp) = options.samples_per_pixel {
+        camera = camera.with_samples_per_pixel(spp);
+    }
+    if let Some(threshold) = options.adaptive {
+        camera = camera.with_adaptive_threshold(threshold);
+    }
+    if let Some(depth) = options.max_depth {
+        camera = camera.with_max_depth(depth);
+    }
+    if let Some(seed) = options.seed {
+        camera = camera.with_seed(seed);
+    }
+    if let Some(exposure) = options.exposure {
+        camera = camera.with_exposure(exposure);
+    }
+    if let Some(kelvin) = options.white_balance {
+        camera = camera.with_white_balance(kelvin);
+    }
+    if let Some(tone_map) = options.tone_map {
+        camera = camera.with_tone_map(tone_map);
+    }
+    if let Some(aovs) = &options.aovs {
+        camera = camera.with_aovs(aovs.clone());
+    }
+    if options.spectral {
+        camera = camera.with_spectral(true);
+    }
+    // the AOVs asked for are written out; the denoiser's guides are only rendered
+    let written: Vec<Aov> = camera.aovs().iter().map(|&aov| aov.into()).collect();
+    if options.denoise {
+        let mut aovs = camera.aovs().to_vec();
+        for guide in [scene::Aov::Albedo, scene::Aov::Normal] {
+            if !aovs.contains(&guide) {
+                aovs.push(guide);
+            }
+        }
+        camera = camera.with_aovs(aovs);
+    }
+    let scene = scene.with_camera(camera);
+    // workers are sent the scene with the overrides applied
+    let json = options
+        .coordinator
+        .is_some()
+        .then(|| serde_json::to_string(&scene).expect("scenes serialize to JSON"));
+
+    let scene_key = scene.key();
+    let (world, lights, camera) = match scene.build() {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut camera = camera::Camera::from(camera).with_scene_key(scene_key);
+    if let Some(region) = options.region {
+        let (width, height) = (camera.image_width(), camera.image_height());
+        if region.x1 > width || region.y1 > height {
+            eprintln!(
+                "error: --region {},{},{},{} is outside the {}x{} image",
+                region.x0, region.y0, region.x1, region.y1, width, height
+            );
+            return ExitCode::from(2);
+        }
+        camera = camera.with_region(region);
+    }
 
     // let (world, lights, cam) = match scene {
     //     1 => random_spheres(400, 100),
@@ -493,5 +766,13 @@ fn main() {
     //     _ => panic!(),
     // };
 
-    camera.render(&world, &lights);
+    let result = match (&options.coordinator, &json) {
+        (Some(addr), Some(json)) => coordinate(addr, json, &camera, &written, &options),
+        _ => render(&camera, &world, &lights, &written, &options),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,235 @@
+use crate::{aabb::Aabb, point3::Point3, vector3::Vector3};
+
+/// A row-major 4x4 matrix for affine transforms of points and vectors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Matrix4 { m }
+    }
+
+    pub fn identity() -> Self {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: &Vector3) -> Self {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scale(s: &Vector3) -> Self {
+        Matrix4::new([
+            [s.x, 0.0, 0.0, 0.0],
+            [0.0, s.y, 0.0, 0.0],
+            [0.0, 0.0, s.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Rotation by `angle` degrees counter-clockwise about `axis`.
+    pub fn rotation(axis: &Vector3, angle: f64) -> Self {
+        let a = axis.unit_vector();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Matrix4::new([
+            [
+                t * a.x * a.x + cos,
+                t * a.x * a.y - sin * a.z,
+                t * a.x * a.z + sin * a.y,
+                0.0,
+            ],
+            [
+                t * a.x * a.y + sin * a.z,
+                t * a.y * a.y + cos,
+                t * a.y * a.z - sin * a.x,
+                0.0,
+            ],
+            [
+                t * a.x * a.z - sin * a.y,
+                t * a.y * a.z + sin * a.x,
+                t * a.z * a.z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_x(angle: f64) -> Self {
+        Matrix4::rotation(&Vector3::new(1.0, 0.0, 0.0), angle)
+    }
+
+    pub fn rotation_y(angle: f64) -> Self {
+        Matrix4::rotation(&Vector3::new(0.0, 1.0, 0.0), angle)
+    }
+
+    pub fn rotation_z(angle: f64) -> Self {
+        Matrix4::rotation(&Vector3::new(0.0, 0.0, 1.0), angle)
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Matrix4::new(m)
+    }
+
+    /// The determinant of the linear part, by how much the transform scales volumes.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. Returns `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let f = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= f * a[col][j];
+                    inv[row][j] -= f * inv[col][j];
+                }
+            }
+        }
+
+        Some(Matrix4::new(inv))
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x / w, y / w, z / w)
+        }
+    }
+
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// The smallest box containing all eight transformed corners of `bbox`.
+    pub fn transform_aabb(&self, bbox: &Aabb) -> Aabb {
+        let mut result = Aabb::new_empty();
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let corner = Point3::new(
+                        if i == 0 { bbox.x.min } else { bbox.x.max },
+                        if j == 0 { bbox.y.min } else { bbox.y.max },
+                        if k == 0 { bbox.z.min } else { bbox.z.max },
+                    );
+                    let p = self.transform_point(&corner);
+                    result = Aabb::new_from_aabbs(&result, &Aabb::new_from_points(&p, &p));
+                }
+            }
+        }
+        result
+    }
+}
+
+impl std::ops::Mul for &Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Matrix4::new(m)
+    }
+}
+
+impl std::ops::Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        &self * &rhs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{point3::Point3, vector3::Vector3};
+
+    use super::Matrix4;
+
+    fn assert_near(a: &Matrix4, b: &Matrix4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a.m[i][j] - b.m[i][j]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inverse() {
+        let m = Matrix4::translation(&Vector3::new(1.0, -2.0, 3.0))
+            * Matrix4::rotation(&Vector3::new(1.0, 1.0, 0.0), 33.0)
+            * Matrix4::scale(&Vector3::new(2.0, 0.5, 4.0));
+        let inv = m.inverse().unwrap();
+        assert_near(&(&m * &inv), &Matrix4::identity());
+        assert_near(&(&inv * &m), &Matrix4::identity());
+        assert!(Matrix4::scale(&Vector3::new(1.0, 0.0, 1.0))
+            .inverse()
+            .is_none());
+        assert!((m.determinant() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let p = Matrix4::rotation_z(90.0).transform_point(&Point3::new(1.0, 0.0, 0.0));
+        assert!((p.x).abs() < 1e-12 && (p.y - 1.0).abs() < 1e-12 && p.z.abs() < 1e-12);
+
+        let p = Matrix4::rotation_x(90.0).transform_point(&Point3::new(0.0, 1.0, 0.0));
+        assert!((p.x).abs() < 1e-12 && p.y.abs() < 1e-12 && (p.z - 1.0).abs() < 1e-12);
+    }
+}
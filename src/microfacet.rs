@@ -0,0 +1,145 @@
+//! The GGX, or Trowbridge-Reitz, distribution of microfacet normals that rough materials share.
+//!
+//! Everything here works in the local frame of the surface, with the normal along z.
+
+use std::f64::consts::PI;
+
+use crate::vector3::Vector3;
+
+/// Below this the distribution is so narrow that the surface is treated as a perfect mirror.
+pub const MIN_ALPHA: f64 = 1e-3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    /// A distribution for perceptual `roughness` between 0 and 1, stretched along the x axis by
+    /// `anisotropy` between 0 and 1, as in Burley's Disney BRDF.
+    pub fn new(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Ggx {
+            alpha_x: (alpha / aspect).max(MIN_ALPHA),
+            alpha_y: (alpha * aspect).max(MIN_ALPHA),
+        }
+    }
+
+    /// Whether the surface is smooth enough to be treated as a perfect mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) <= MIN_ALPHA
+    }
+
+    /// The density of microfacets with normal `m`.
+    pub fn d(&self, m: &Vector3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    /// Smith's Λ, the shadowed fraction of the microfacets facing `w` relative to the lit one.
+    fn lambda(&self, w: &Vector3) -> f64 {
+        let tan2 = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        (-1.0 + (1.0 + tan2).sqrt()) / 2.0
+    }
+
+    /// The fraction of the microfacets facing `w` that `w` sees.
+    pub fn g1(&self, w: &Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of the microfacets that both `wo` and `wi` see.
+    pub fn g2(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// A microfacet normal seen from `wo`, drawn in proportion to how much of it `wo` sees, from
+    /// Heitz's "Sampling the GGX Distribution of Visible Normals".
+    pub fn sample_visible(&self, wo: &Vector3, u1: f64, u2: f64) -> Vector3 {
+        // stretch the view so the distribution becomes a hemisphere
+        let vh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vector3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        // a point on the disk, squashed onto the part of it the view sees
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * &t1 + p2 * &t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * &vh;
+
+        Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(0.0)).unit_vector()
+    }
+
+    /// The density of `wi` when it is `wo` reflected in a normal from `sample_visible`.
+    pub fn reflection_pdf(&self, wo: &Vector3, wi: &Vector3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).unit_vector();
+        self.g1(wo) * self.d(&m) / (4.0 * wo.z)
+    }
+}
+
+/// `wo` reflected in the microfacet with normal `m`.
+pub fn reflect(wo: &Vector3, m: &Vector3) -> Vector3 {
+    &(2.0 * wo.dot(m) * m) - wo
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use crate::{random::SampleRng, vector3::Vector3};
+
+    use super::{reflect, Ggx};
+
+    #[test]
+    fn test_ggx() {
+        let mut rng = SampleRng::seed_from_u64(3);
+        for ggx in [Ggx::new(0.5, 0.0), Ggx::new(0.3, 0.8)] {
+            // the projected microfacet area is the macrosurface's
+            let n = 200_000;
+            let d: f64 = (0..n)
+                // with m drawn in proportion to its cosine, D cos / pdf is D π
+                .map(|_| ggx.d(&Vector3::random_cosine_dir(&mut rng)) * std::f64::consts::PI)
+                .sum::<f64>()
+                / n as f64;
+            assert!((d - 1.0).abs() < 0.02, "{:?} integrates to {}", ggx, d);
+
+            // sampled directions follow reflection_pdf: E[1 / pdf] over the hemisphere is its
+            // solid angle, counting only the directions that stay above the surface
+            let wo = Vector3::new(0.4, -0.2, 0.8).unit_vector();
+            let (mut estimate, mut below) = (0.0, 0);
+            for _ in 0..n {
+                let m = ggx.sample_visible(&wo, rng.gen(), rng.gen());
+                let wi = reflect(&wo, &m);
+                if wi.z <= 0.0 {
+                    below += 1;
+                    continue;
+                }
+                // f = D G2 / (4 wo.z wi.z), so f wi.z / pdf = G2 / G1 <= 1
+                let pdf = ggx.reflection_pdf(&wo, &wi);
+                estimate += ggx.d(&m) * ggx.g2(&wo, &wi) / (4.0 * wo.z) / pdf;
+            }
+            let albedo = estimate / n as f64;
+            assert!(
+                albedo > 0.8 && albedo <= 1.0,
+                "{:?} reflects {}",
+                ggx,
+                albedo
+            );
+            assert!(below < n / 10);
+        }
+    }
+}
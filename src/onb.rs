@@ -19,10 +19,28 @@ impl Onb {
         Onb { u, v, w: unit_w }
     }
 
+    /// The frame around `w` whose u axis follows `tangent`, made perpendicular to `w`, or any
+    /// frame around `w` if `tangent` is zero or along it.
+    pub fn new_with_tangent(w: &Vector3, tangent: &Vector3) -> Self {
+        let unit_w = w.unit_vector();
+        let t = tangent - tangent.dot(&unit_w) * &unit_w;
+        if t.length_squared() <= 1e-12 * tangent.length_squared() {
+            return Onb::new(w);
+        }
+        let u = t.unit_vector();
+        let v = unit_w.cross(&u);
+        Onb { u, v, w: unit_w }
+    }
+
     pub fn local(&self, a: &Vector3) -> Vector3 {
         a.x * &self.u + a.y * &self.v + a.z * &self.w
     }
 
+    /// The coordinates of `a` in this basis, undoing `local`.
+    pub fn to_local(&self, a: &Vector3) -> Vector3 {
+        Vector3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+
     pub fn local_axes(&self, a: f64, b: f64, c: f64) -> Vector3 {
         a * &self.u + b * &self.v + c * &self.v
     }
@@ -0,0 +1,178 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding},
+    error::{ImageFormatHint, UnsupportedError},
+    ColorType, ImageEncoder, ImageError, ImageFormat, ImageResult, Rgb32FImage, RgbImage,
+};
+
+use crate::{framebuffer::Framebuffer, tonemap::ToneMap};
+
+/// Image file formats a `Framebuffer` can be written to.
+///
+/// `Png`, `Jpeg` and `Ppm` are 8-bit, tone mapped and sRGB encoded; `Exr` and `Pfm` store the
+/// linear radiance as 32-bit floats, with only exposure and white balance applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Ppm,
+    Exr,
+    Pfm,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::Png),
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "ppm" => Some(Format::Ppm),
+            "exr" => Some(Format::Exr),
+            "pfm" => Some(Format::Pfm),
+            _ => None,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Format> {
+        path.as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_name)
+    }
+}
+
+/// Writes `fb` to `path` through `tone_map`. The format is taken from `format` if given,
+/// otherwise from the file extension, falling back to PNG only if there is none. An extension
+/// that isn't one of the formats is an error rather than an image under the wrong name.
+///
+/// The image is written to a hidden file next to `path` and renamed over it, so whatever is at
+/// `path` is always a complete image, even if the process is killed while writing a checkpoint.
+pub fn write<P: AsRef<Path>>(
+    fb: &Framebuffer,
+    path: P,
+    format: Option<Format>,
+    tone_map: &ToneMap,
+) -> ImageResult<()> {
+    let path = path.as_ref();
+    let format = match (format, path.extension()) {
+        (Some(format), _) => format,
+        (None, None) => Format::Png,
+        (None, Some(ext)) => Format::from_path(path).ok_or_else(|| {
+            ImageError::Unsupported(UnsupportedError::from(ImageFormatHint::PathExtension(
+                ext.into(),
+            )))
+        })?,
+    };
+
+    let partial = partial_path(path);
+    let result = match format {
+        Format::Png => to_rgb8(fb, tone_map).save_with_format(&partial, ImageFormat::Png),
+        Format::Jpeg => to_rgb8(fb, tone_map).save_with_format(&partial, ImageFormat::Jpeg),
+        Format::Ppm => write_ppm(fb, tone_map, &partial),
+        Format::Exr => to_rgb32f(fb, tone_map).save_with_format(&partial, ImageFormat::OpenExr),
+        Format::Pfm => write_pfm(fb, tone_map, &partial),
+    };
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    Ok(fs::rename(&partial, path)?)
+}
+
+/// Where the image of AOV `name` goes when the image goes to `path`: `image.png` puts the
+/// albedo in `image.albedo.png`.
+pub fn aov_path<P: AsRef<Path>>(path: P, name: &str) -> PathBuf {
+    let path = path.as_ref();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, name, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}.{}", stem, name)),
+    }
+}
+
+/// The hidden file next to `path` that is written first and then renamed over it.
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.partial", name))
+}
+
+fn to_rgb8(fb: &Framebuffer, tone_map: &ToneMap) -> RgbImage {
+    let data = fb
+        .pixels()
+        .iter()
+        .flat_map(|c| tone_map.display(c).to_rgb8())
+        .collect();
+    RgbImage::from_raw(fb.width, fb.height, data).unwrap()
+}
+
+fn to_rgb32f(fb: &Framebuffer, tone_map: &ToneMap) -> Rgb32FImage {
+    let data = fb
+        .pixels()
+        .iter()
+        .map(|c| tone_map.expose(c))
+        .flat_map(|c| [c.r as f32, c.g as f32, c.b as f32])
+        .collect();
+    Rgb32FImage::from_raw(fb.width, fb.height, data).unwrap()
+}
+
+fn write_ppm(fb: &Framebuffer, tone_map: &ToneMap, path: &Path) -> ImageResult<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    PnmEncoder::new(writer)
+        .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary))
+        .write_image(
+            to_rgb8(fb, tone_map).as_raw(),
+            fb.width,
+            fb.height,
+            ColorType::Rgb8,
+        )
+}
+
+fn write_pfm(fb: &Framebuffer, tone_map: &ToneMap, path: &Path) -> ImageResult<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // a negative scale marks the samples as little-endian
+    write!(writer, "PF\n{} {}\n-1.0\n", fb.width, fb.height)?;
+
+    // PFM scanlines run from the bottom of the image to the top
+    for y in (0..fb.height).rev() {
+        for x in 0..fb.width {
+            let c = tone_map.expose(fb.get(x, y));
+            for v in [c.r as f32, c.g as f32, c.b as f32] {
+                writer.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::{color::Color, framebuffer::Framebuffer, tonemap::ToneMap};
+
+    use super::{write, Format};
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("out.png"), Some(Format::Png));
+        assert_eq!(Format::from_path("out.JPG"), Some(Format::Jpeg));
+        assert_eq!(Format::from_path("renders/out.ppm"), Some(Format::Ppm));
+        assert_eq!(Format::from_path("out.exr"), Some(Format::Exr));
+        assert_eq!(Format::from_path("out.pfm"), Some(Format::Pfm));
+        assert_eq!(Format::from_path("out.txt"), None);
+        assert_eq!(Format::from_path("out"), None);
+    }
+
+    #[test]
+    fn test_write_rejects_unknown_extension() {
+        let fb = Framebuffer::from_pixels(1, 1, vec![Color::new(0.5, 0.5, 0.5)]);
+        let path = env::temp_dir().join(format!("raytracing-rs-{}.tif", std::process::id()));
+        assert!(write(&fb, &path, None, &ToneMap::default()).is_err());
+        assert!(!path.exists());
+    }
+}
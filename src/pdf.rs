@@ -2,13 +2,15 @@ use std::f64::consts::PI;
 
 use rand::Rng;
 
-use crate::{hittable, onb::Onb, point3::Point3, vector3::Vector3};
+use crate::{hittable, microfacet, onb::Onb, point3::Point3, vector3::Vector3};
 
 pub enum Pdf<'a> {
     Sphere(Sphere),
     Cosine(Cosine),
+    Ggx(Ggx),
     Hittable(Hittable<'a>),
     Mixture(Mixture<'a>),
+    Blend(Blend<'a>),
 }
 
 impl Pdf<'_> {
@@ -16,17 +18,21 @@ impl Pdf<'_> {
         match self {
             Pdf::Sphere(s) => s.value(dir),
             Pdf::Cosine(c) => c.value(dir),
+            Pdf::Ggx(g) => g.value(dir),
             Pdf::Hittable(h) => h.value(dir),
             Pdf::Mixture(m) => m.value(dir),
+            Pdf::Blend(b) => b.value(dir),
         }
     }
 
-    pub fn generate(&self) -> Vector3 {
+    pub fn generate(&self, rng: &mut impl Rng) -> Vector3 {
         match self {
-            Pdf::Sphere(s) => s.generate(),
-            Pdf::Cosine(c) => c.generate(),
-            Pdf::Hittable(h) => h.generate(),
-            Pdf::Mixture(m) => m.generate(),
+            Pdf::Sphere(s) => s.generate(rng),
+            Pdf::Cosine(c) => c.generate(rng),
+            Pdf::Ggx(g) => g.generate(rng),
+            Pdf::Hittable(h) => h.generate(rng),
+            Pdf::Mixture(m) => m.generate(rng),
+            Pdf::Blend(b) => b.generate(rng),
         }
     }
 }
@@ -34,6 +40,7 @@ impl Pdf<'_> {
 pub struct Sphere {}
 
 impl<'a> Sphere {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Pdf<'a> {
         Pdf::Sphere(Sphere {})
     }
@@ -42,8 +49,8 @@ impl<'a> Sphere {
         1.0 / (4.0 * PI)
     }
 
-    fn generate(&self) -> Vector3 {
-        Vector3::random_unit_vector()
+    fn generate(&self, rng: &mut impl Rng) -> Vector3 {
+        Vector3::random_unit_vector(rng)
     }
 }
 
@@ -52,7 +59,8 @@ pub struct Cosine {
 }
 
 impl Cosine {
-    pub fn new(w: &Vector3) -> Pdf {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(w: &Vector3) -> Pdf<'_> {
         Pdf::Cosine(Cosine { uvw: Onb::new(w) })
     }
 
@@ -61,8 +69,41 @@ impl Cosine {
         0.0_f64.max(cos_theta / PI)
     }
 
-    fn generate(&self) -> Vector3 {
-        self.uvw.local(&Vector3::random_cosine_dir())
+    fn generate(&self, rng: &mut impl Rng) -> Vector3 {
+        self.uvw.local(&Vector3::random_cosine_dir(rng))
+    }
+}
+
+/// Reflections of `wo` in the microfacets it can see, which follow a GGX BRDF closely.
+pub struct Ggx {
+    uvw: Onb,
+    /// The direction back along the incoming ray, in the `uvw` frame.
+    wo: Vector3,
+    distribution: microfacet::Ggx,
+}
+
+impl Ggx {
+    /// The pdf for a ray arriving along `dir_in` at a surface with shading frame `uvw`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new<'a>(uvw: Onb, dir_in: &Vector3, distribution: microfacet::Ggx) -> Pdf<'a> {
+        let wo = uvw.to_local(&-dir_in.unit_vector());
+        Pdf::Ggx(Ggx {
+            uvw,
+            wo,
+            distribution,
+        })
+    }
+
+    fn value(&self, dir: &Vector3) -> f64 {
+        let wi = self.uvw.to_local(&dir.unit_vector());
+        self.distribution.reflection_pdf(&self.wo, &wi)
+    }
+
+    fn generate(&self, rng: &mut impl Rng) -> Vector3 {
+        let m = self
+            .distribution
+            .sample_visible(&self.wo, rng.gen(), rng.gen());
+        self.uvw.local(&microfacet::reflect(&self.wo, &m))
     }
 }
 
@@ -72,7 +113,8 @@ pub struct Hittable<'a> {
 }
 
 impl Hittable<'_> {
-    pub fn new(objects: &hittable::Hittable, orig: Point3) -> Pdf {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(objects: &hittable::Hittable, orig: Point3) -> Pdf<'_> {
         Pdf::Hittable(Hittable { objects, orig })
     }
 
@@ -80,8 +122,8 @@ impl Hittable<'_> {
         self.objects.pdf_value(&self.orig, dir)
     }
 
-    fn generate(&self) -> Vector3 {
-        self.objects.random(&self.orig)
+    fn generate(&self, rng: &mut impl Rng) -> Vector3 {
+        self.objects.random(&self.orig, rng)
     }
 }
 
@@ -91,6 +133,7 @@ pub struct Mixture<'a> {
 }
 
 impl Mixture<'_> {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new<'a>(a: &'a Pdf<'a>, b: &'a Pdf<'a>) -> Pdf<'a> {
         Pdf::Mixture(Mixture { a, b })
     }
@@ -99,12 +142,54 @@ impl Mixture<'_> {
         0.5 * self.a.value(dir) + 0.5 * self.b.value(dir)
     }
 
-    fn generate(&self) -> Vector3 {
-        let mut rng = rand::thread_rng();
+    fn generate(&self, rng: &mut impl Rng) -> Vector3 {
         if rng.gen::<f64>() < 0.5 {
-            self.a.generate()
+            self.a.generate(rng)
         } else {
-            self.b.generate()
+            self.b.generate(rng)
+        }
+    }
+}
+
+/// Several pdfs, each drawn from in proportion to its weight, such as the lobes of a layered
+/// material.
+pub struct Blend<'a> {
+    pdfs: Vec<(f64, Pdf<'a>)>,
+    total: f64,
+}
+
+impl Blend<'_> {
+    /// The weights must not be negative, and at least one must be positive.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(pdfs: Vec<(f64, Pdf<'_>)>) -> Pdf<'_> {
+        let total = pdfs.iter().map(|(w, _)| w).sum();
+        Pdf::Blend(Blend { pdfs, total })
+    }
+
+    fn value(&self, dir: &Vector3) -> f64 {
+        self.pdfs
+            .iter()
+            .filter(|(w, _)| *w > 0.0)
+            .map(|(w, pdf)| w * pdf.value(dir))
+            .sum::<f64>()
+            / self.total
+    }
+
+    fn generate(&self, rng: &mut impl Rng) -> Vector3 {
+        let mut u = rng.gen::<f64>() * self.total;
+        for (w, pdf) in &self.pdfs {
+            if u < *w {
+                return pdf.generate(rng);
+            }
+            u -= w;
         }
+        // rounding can leave u just above the last weight
+        let (_, pdf) = self
+            .pdfs
+            .iter()
+            .rev()
+            .find(|(w, _)| *w > 0.0)
+            .expect("a blend has a positive weight");
+        pdf.generate(rng)
     }
 }
@@ -13,15 +13,15 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    pub fn new() -> Self {
+    pub fn new(rng: &mut impl Rng) -> Self {
         let ranvec = (0..POINT_COUNT)
-            .map(|_| Vector3::random(-1.0, 1.0))
+            .map(|_| Vector3::random(-1.0, 1.0, rng))
             .collect();
         Perlin {
             ranvec,
-            perm_x: perlin_generate_perm(),
-            perm_y: perlin_generate_perm(),
-            perm_z: perlin_generate_perm(),
+            perm_x: perlin_generate_perm(rng),
+            perm_y: perlin_generate_perm(rng),
+            perm_z: perlin_generate_perm(rng),
         }
     }
 
@@ -74,20 +74,17 @@ impl Perlin {
     }
 }
 
-fn perlin_generate_perm() -> Vec<usize> {
+fn perlin_generate_perm(rng: &mut impl Rng) -> Vec<usize> {
     let mut p = (0..POINT_COUNT).collect::<Vec<usize>>();
-    permute(&mut p, POINT_COUNT);
+    permute(&mut p, POINT_COUNT, rng);
 
     p
 }
 
-fn permute(p: &mut Vec<usize>, n: usize) {
-    let mut rng = rand::thread_rng();
-    for i in 1..n as usize {
+fn permute(p: &mut [usize], n: usize, rng: &mut impl Rng) {
+    for i in 1..n {
         let target = rng.gen_range(0..i);
-        let tmp = p[i];
-        p[i] = p[target];
-        p[target] = tmp;
+        p.swap(i, target);
     }
 }
 
@@ -97,9 +94,9 @@ fn perlin_interp(c: &[[[Vector3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
     let ww = w * w * (3.0 - 2.0 * w);
     let mut accum = 0.0;
 
-    for i in 0..2 {
-        for j in 0..2 {
-            for k in 0..2 {
+    for (i, ci) in c.iter().enumerate() {
+        for (j, cij) in ci.iter().enumerate() {
+            for (k, cijk) in cij.iter().enumerate() {
                 let i_f = i as f64;
                 let j_f = j as f64;
                 let k_f = k as f64;
@@ -107,7 +104,7 @@ fn perlin_interp(c: &[[[Vector3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
                 accum += (i_f * uu + (1.0 - i_f) * (1.0 - uu))
                     * (j_f * vv + (1.0 - j_f) * (1.0 - vv))
                     * (k_f * ww + (1.0 - k_f) * (1.0 - ww))
-                    * c[i][j][k].dot(&weight_v);
+                    * cijk.dot(&weight_v);
             }
         }
     }
@@ -1,3 +1,4 @@
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::vector3::Vector3;
@@ -14,8 +15,8 @@ impl Point3 {
         Point3 { x, y, z }
     }
 
-    pub fn random(min: f64, max: f64) -> Self {
-        Vector3::random(min, max).into()
+    pub fn random(min: f64, max: f64, rng: &mut impl Rng) -> Self {
+        Vector3::random(min, max, rng).into()
     }
 }
 
@@ -0,0 +1,33 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+
+/// The generator everything random in a render draws from.
+pub type SampleRng = Pcg32;
+
+/// A generator for one camera sample. It is seeded from the render seed, the pixel and the sample
+/// index alone, so the result doesn't depend on which thread renders the pixel or when.
+pub fn sample_rng(seed: u64, x: u32, y: u32, sample: usize) -> SampleRng {
+    SampleRng::seed_from_u64(hash(&[seed, pixel_key(x, y), sample as u64]))
+}
+
+pub(crate) fn pixel_key(x: u32, y: u32) -> u64 {
+    (y as u64) << 32 | x as u64
+}
+
+/// Combines `values` into one well mixed 64-bit hash.
+pub(crate) fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0, |h, &v| mix(h ^ v))
+}
+
+/// A hash of the bytes of `s`.
+pub(crate) fn hash_str(s: &str) -> u64 {
+    hash(&s.bytes().map(u64::from).collect::<Vec<_>>())
+}
+
+/// The SplitMix64 finalizer, so nearby pixels and samples get unrelated seeds.
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -1,15 +1,38 @@
-use crate::{point3::Point3, vector3::Vector3};
+use crate::{color::Color, point3::Point3, spectrum::Wavelengths, vector3::Vector3};
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vector3,
     pub time: f64,
+    /// The wavelengths the path is traced at, in spectral mode.
+    pub wavelengths: Option<Wavelengths>,
+    /// The fraction of each channel absorbed per unit distance by the glass the path is inside,
+    /// as it was where the path entered.
+    pub absorption: Option<Color>,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vector3, time: f64) -> Self {
-        Ray { orig, dir, time }
+        Ray {
+            orig,
+            dir,
+            time,
+            wavelengths: None,
+            absorption: None,
+        }
+    }
+
+    /// The next ray of the same path, from `orig` along `dir` at this ray's time and wavelengths
+    /// and inside the same glass.
+    pub fn bounce(&self, orig: Point3, dir: Vector3) -> Self {
+        Ray {
+            orig,
+            dir,
+            time: self.time,
+            wavelengths: self.wavelengths,
+            absorption: self.absorption.clone(),
+        }
     }
 
     pub fn at(&self, t: f64) -> Point3 {
@@ -0,0 +1,314 @@
+use rand::{Rng, RngCore};
+
+use crate::random::{self, SampleRng};
+
+/// Primes for the Halton bases; dimensions past the last one fall back to independent samples.
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Direction numbers of the second Sobol dimension. The first is the bit-reversed index.
+const SOBOL_1: [u32; 32] = sobol_1_directions();
+
+/// The largest `f64` below one, so sequence values never round up to the end of the interval.
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// How the samples of a pixel are spread over the pixel, the lens, the shutter interval and each
+/// bounce.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sampler {
+    /// Independent uniform random numbers.
+    #[default]
+    Independent,
+    /// One jittered sample per cell of a grid over each pair of dimensions, visiting the cells in
+    /// a different random order for every pixel and pair.
+    Stratified,
+    /// The Halton sequence, shifted by a random Cranley-Patterson rotation per pixel.
+    Halton,
+    /// The two-dimensional Sobol sequence, shuffled and Owen scrambled for every pixel and pair
+    /// of dimensions.
+    Sobol,
+}
+
+impl Sampler {
+    /// The stream for sample `index` of `samples_per_pixel` in pixel (`x`, `y`). The number of
+    /// samples sets the stratified grid; later indices start a new, differently ordered grid.
+    pub fn stream(
+        self,
+        seed: u64,
+        x: u32,
+        y: u32,
+        index: usize,
+        samples_per_pixel: usize,
+    ) -> SampleStream {
+        SampleStream {
+            sampler: self,
+            pixel_seed: random::hash(&[seed, random::pixel_key(x, y)]),
+            index,
+            samples_per_pixel: samples_per_pixel.max(1),
+            dimension: 0,
+            pending: None,
+            rng: random::sample_rng(seed, x, y, index),
+        }
+    }
+}
+
+/// The sample values for one camera sample, handed out a pair of dimensions at a time.
+///
+/// It implements `RngCore` so materials, PDFs and media draw from the same sequence through the
+/// usual `Rng` methods: `gen::<f64>()` returns the next one-dimensional value unchanged.
+pub struct SampleStream {
+    sampler: Sampler,
+    pixel_seed: u64,
+    index: usize,
+    samples_per_pixel: usize,
+    dimension: u64,
+    /// The second half of a pair whose first half was returned by `get_1d`.
+    pending: Option<f64>,
+    rng: SampleRng,
+}
+
+impl SampleStream {
+    pub fn get_1d(&mut self) -> f64 {
+        if self.sampler == Sampler::Independent {
+            return self.rng.gen();
+        }
+        if let Some(v) = self.pending.take() {
+            return v;
+        }
+        let (a, b) = self.get_2d();
+        self.pending = Some(b);
+        a
+    }
+
+    pub fn get_2d(&mut self) -> (f64, f64) {
+        self.pending = None;
+        let dimension = self.dimension;
+        self.dimension += 2;
+
+        match self.sampler {
+            Sampler::Independent => (self.rng.gen(), self.rng.gen()),
+            Sampler::Stratified => self.stratified(dimension),
+            Sampler::Halton => (self.halton(dimension), self.halton(dimension + 1)),
+            Sampler::Sobol => self.sobol(dimension),
+        }
+    }
+
+    fn stratified(&mut self, dimension: u64) -> (f64, f64) {
+        let nx = (self.samples_per_pixel as f64).sqrt().ceil() as usize;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let cells = nx * ny;
+
+        let round = (self.index / cells) as u64;
+        let seed = random::hash(&[self.pixel_seed, dimension, round]) as u32;
+        let cell = permute((self.index % cells) as u32, cells as u32, seed) as usize;
+
+        (
+            ((cell % nx) as f64 + self.rng.gen::<f64>()) / nx as f64,
+            ((cell / nx) as f64 + self.rng.gen::<f64>()) / ny as f64,
+        )
+    }
+
+    fn halton(&mut self, dimension: u64) -> f64 {
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            return self.rng.gen();
+        };
+        let shift = unit_f64(random::hash(&[self.pixel_seed, dimension]));
+        let v = radical_inverse(base, self.index as u64) + shift;
+        if v >= 1.0 {
+            v - 1.0
+        } else {
+            v
+        }
+    }
+
+    fn sobol(&mut self, dimension: u64) -> (f64, f64) {
+        let seed = random::hash(&[self.pixel_seed, dimension]);
+        let index = nested_uniform_scramble(self.index as u32, seed as u32);
+
+        let x = nested_uniform_scramble(index.reverse_bits(), (seed >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_1(index), random::hash(&[seed]) as u32);
+        (unit_f32(x), unit_f32(y))
+    }
+}
+
+impl RngCore for SampleStream {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // `Rng::gen::<f64>()` keeps the top 53 bits, so put the sample value there
+        ((self.get_1d() * (1u64 << 53) as f64) as u64) << 11
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Maps the unit square onto the unit disk, keeping neighbouring samples together so the
+/// stratification of `(u, v)` carries over to the lens.
+pub fn concentric_disk(u: f64, v: f64) -> (f64, f64) {
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+fn unit_f64(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn unit_f32(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+fn radical_inverse(base: u64, mut a: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut reversed = 0;
+    let mut inv_base_n = 1.0;
+    while a > 0 {
+        let next = a / base;
+        reversed = reversed * base + (a - next * base);
+        inv_base_n *= inv_base;
+        a = next;
+    }
+    (reversed as f64 * inv_base_n).min(ONE_MINUS_EPSILON)
+}
+
+/// Kensler's hash-based permutation of `0..len`, from "Correlated Multi-Jittered Sampling".
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % len
+}
+
+const fn sobol_1_directions() -> [u32; 32] {
+    let mut v = [0; 32];
+    v[0] = 1 << 31;
+    let mut i = 1;
+    while i < 32 {
+        v[i] = v[i - 1] ^ (v[i - 1] >> 1);
+        i += 1;
+    }
+    v
+}
+
+fn sobol_1(index: u32) -> u32 {
+    (0..32)
+        .filter(|bit| index >> bit & 1 == 1)
+        .fold(0, |x, bit| x ^ SOBOL_1[bit])
+}
+
+/// Owen scrambling of the bits of `x`, from Burley's "Practical Hash-based Owen Scrambling".
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::Sampler;
+
+    /// Whether the first two dimensions put exactly one of `n * n` samples in each cell of an
+    /// `n` by `n` grid.
+    fn one_per_cell(sampler: Sampler, n: usize) -> bool {
+        let spp = n * n;
+        let mut counts = vec![0; spp];
+        for s in 0..spp {
+            let (u, v) = sampler.stream(1, 3, 4, s, spp).get_2d();
+            counts[(v * n as f64) as usize * n + (u * n as f64) as usize] += 1;
+        }
+        counts.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn test_stratification() {
+        assert!(one_per_cell(Sampler::Stratified, 4));
+        assert!(one_per_cell(Sampler::Sobol, 4));
+        assert!(one_per_cell(Sampler::Sobol, 8));
+        assert!(!one_per_cell(Sampler::Independent, 8));
+
+        // Halton's first dimension is stratified into the 16 elementary intervals of base 2
+        let mut counts = [0; 16];
+        for s in 0..16 {
+            counts[(Sampler::Halton.stream(1, 3, 4, s, 16).get_1d() * 16.0) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn test_stream_as_rng() {
+        for sampler in [
+            Sampler::Independent,
+            Sampler::Stratified,
+            Sampler::Halton,
+            Sampler::Sobol,
+        ] {
+            let mut a = sampler.stream(9, 0, 0, 5, 16);
+            let mut b = sampler.stream(9, 0, 0, 5, 16);
+            for _ in 0..100 {
+                let x = a.get_1d();
+                assert!((0.0..1.0).contains(&x));
+                // only the rounding to 53 bits of the round trip through `next_u64` may differ
+                assert!((x - b.gen::<f64>()).abs() < 1e-15);
+            }
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{camera, color::Color, point3::Point3, vector3::Vector3};
+use crate::{
+    aov, camera, color::Color, filter, point3::Point3, sampler, tile, tonemap, vector3::Vector3,
+};
 
-#[derive(Serialize, Deserialize)]
+use super::validate::Validator;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Camera {
     image_width: u32,
     aspect_ratio: f64,
@@ -15,9 +19,319 @@ pub struct Camera {
     samples_per_pixel: usize,
     max_depth: u32,
     background: Color,
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    sampler: Sampler,
+    #[serde(default)]
+    filter: Filter,
+    /// When present, `samples_per_pixel` is the average over the image and the noisiest pixels
+    /// take more than the rest.
+    #[serde(default)]
+    adaptive: Option<Adaptive>,
+    #[serde(default = "default_tile_size")]
+    tile_size: u32,
+    #[serde(default)]
+    tile_order: TileOrder,
+    /// Auxiliary images to record alongside the image.
+    #[serde(default)]
+    aovs: Vec<Aov>,
+    /// In stops: each one doubles the brightness.
+    #[serde(default)]
+    exposure: f64,
+    /// The color temperature, in Kelvin, of the light that should come out white.
+    #[serde(default)]
+    white_balance: Option<f64>,
+    #[serde(default)]
+    tone_map: ToneMap,
+    /// Traces each sample at a wavelength, for dispersion.
+    #[serde(default)]
+    spectral: bool,
+}
+
+fn default_tile_size() -> u32 {
+    32
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum TileOrder {
+    Scanline,
+    #[default]
+    Spiral,
+    Hilbert,
+}
+
+impl From<TileOrder> for tile::TileOrder {
+    fn from(value: TileOrder) -> Self {
+        match value {
+            TileOrder::Scanline => tile::TileOrder::Scanline,
+            TileOrder::Spiral => tile::TileOrder::Spiral,
+            TileOrder::Hilbert => tile::TileOrder::Hilbert,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ToneMap {
+    #[default]
+    Clip,
+    Reinhard,
+    Aces,
+    Agx,
+}
+
+impl ToneMap {
+    /// Looks an operator up by its name in lower case, as the command line names them.
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name {
+            "clip" => Some(ToneMap::Clip),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" => Some(ToneMap::Aces),
+            "agx" => Some(ToneMap::Agx),
+            _ => None,
+        }
+    }
+}
+
+impl From<ToneMap> for tonemap::Operator {
+    fn from(value: ToneMap) -> Self {
+        match value {
+            ToneMap::Clip => tonemap::Operator::Clip,
+            ToneMap::Reinhard => tonemap::Operator::Reinhard,
+            ToneMap::Aces => tonemap::Operator::Aces,
+            ToneMap::Agx => tonemap::Operator::Agx,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    Uv,
+    ObjectId,
+    MaterialId,
+}
+
+impl Aov {
+    /// Looks an AOV up by `aov::Aov::name`, as the command line names them.
+    pub fn from_name(name: &str) -> Option<Aov> {
+        let aov = match aov::Aov::from_name(name)? {
+            aov::Aov::Albedo => Aov::Albedo,
+            aov::Aov::Normal => Aov::Normal,
+            aov::Aov::Depth => Aov::Depth,
+            aov::Aov::Position => Aov::Position,
+            aov::Aov::Uv => Aov::Uv,
+            aov::Aov::ObjectId => Aov::ObjectId,
+            aov::Aov::MaterialId => Aov::MaterialId,
+        };
+        Some(aov)
+    }
+}
+
+impl From<Aov> for aov::Aov {
+    fn from(value: Aov) -> Self {
+        match value {
+            Aov::Albedo => aov::Aov::Albedo,
+            Aov::Normal => aov::Aov::Normal,
+            Aov::Depth => aov::Aov::Depth,
+            Aov::Position => aov::Aov::Position,
+            Aov::Uv => aov::Aov::Uv,
+            Aov::ObjectId => aov::Aov::ObjectId,
+            Aov::MaterialId => aov::Aov::MaterialId,
+        }
+    }
+}
+
+/// Adaptive sampling: each pixel stops once the relative standard error of its luminance is
+/// below `threshold`, taking between `min_samples` and `max_samples` samples. The samples the
+/// converged pixels leave of the budget go to the pixels with the highest error.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Adaptive {
+    threshold: f64,
+    #[serde(default = "default_min_samples")]
+    min_samples: usize,
+    max_samples: usize,
+}
+
+fn default_min_samples() -> usize {
+    16
+}
+
+impl Adaptive {
+    pub fn new(threshold: f64, min_samples: usize, max_samples: usize) -> Self {
+        Adaptive {
+            threshold,
+            min_samples,
+            max_samples,
+        }
+    }
+
+    fn validate(&self, path: &str, v: &mut Validator) {
+        let field = |name: &str| format!("{}.{}", path, name);
+        if self.threshold <= 0.0 {
+            v.report(
+                &field("threshold"),
+                format!("must be positive, got {}", self.threshold),
+            );
+        }
+        if self.min_samples < 2 {
+            v.report(
+                &field("min_samples"),
+                format!(
+                    "must be at least 2 to estimate the variance, got {}",
+                    self.min_samples
+                ),
+            );
+        }
+        if self.max_samples < self.min_samples {
+            v.report(
+                &field("max_samples"),
+                format!(
+                    "must be at least min_samples ({}), got {}",
+                    self.min_samples, self.max_samples
+                ),
+            );
+        }
+    }
+}
+
+impl From<Adaptive> for camera::Adaptive {
+    fn from(value: Adaptive) -> Self {
+        camera::Adaptive {
+            threshold: value.threshold,
+            min_samples: value.min_samples,
+            max_samples: value.max_samples,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Sampler {
+    #[default]
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+/// Reconstruction filter. Every parameter is optional, so `{ "Mitchell": {} }` gives the usual
+/// Mitchell-Netravali filter.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum Filter {
+    Box {
+        #[serde(default = "half")]
+        radius: f64,
+    },
+    Tent {
+        #[serde(default = "one")]
+        radius: f64,
+    },
+    Gaussian {
+        #[serde(default = "one_and_a_half")]
+        radius: f64,
+        #[serde(default = "half")]
+        sigma: f64,
+    },
+    Mitchell {
+        #[serde(default = "two")]
+        radius: f64,
+        #[serde(default = "one_third")]
+        b: f64,
+        #[serde(default = "one_third")]
+        c: f64,
+    },
+    Lanczos {
+        #[serde(default = "three")]
+        radius: f64,
+        #[serde(default = "three")]
+        tau: f64,
+    },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: half() }
+    }
+}
+
+fn half() -> f64 {
+    0.5
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn one_and_a_half() -> f64 {
+    1.5
+}
+
+fn two() -> f64 {
+    2.0
+}
+
+fn three() -> f64 {
+    3.0
+}
+
+fn one_third() -> f64 {
+    1.0 / 3.0
+}
+
+impl Filter {
+    fn validate(&self, path: &str, v: &mut Validator) {
+        let (name, radius, width) = match *self {
+            Filter::Box { radius } => ("Box", radius, None),
+            Filter::Tent { radius } => ("Tent", radius, None),
+            Filter::Gaussian { radius, sigma } => ("Gaussian", radius, Some(("sigma", sigma))),
+            Filter::Mitchell { radius, .. } => ("Mitchell", radius, None),
+            Filter::Lanczos { radius, tau } => ("Lanczos", radius, Some(("tau", tau))),
+        };
+        if radius <= 0.0 {
+            v.report(
+                &format!("{}.{}.radius", path, name),
+                format!("must be positive, got {}", radius),
+            );
+        }
+        if let Some((field, value)) = width {
+            if value <= 0.0 {
+                v.report(
+                    &format!("{}.{}.{}", path, name, field),
+                    format!("must be positive, got {}", value),
+                );
+            }
+        }
+    }
+}
+
+impl From<Filter> for filter::Filter {
+    fn from(value: Filter) -> Self {
+        match value {
+            Filter::Box { radius } => filter::Filter::Box { radius },
+            Filter::Tent { radius } => filter::Filter::Tent { radius },
+            Filter::Gaussian { radius, sigma } => filter::Filter::Gaussian { radius, sigma },
+            Filter::Mitchell { radius, b, c } => filter::Filter::Mitchell { radius, b, c },
+            Filter::Lanczos { radius, tau } => filter::Filter::Lanczos { radius, tau },
+        }
+    }
+}
+
+impl From<Sampler> for sampler::Sampler {
+    fn from(value: Sampler) -> Self {
+        match value {
+            Sampler::Independent => sampler::Sampler::Independent,
+            Sampler::Stratified => sampler::Sampler::Stratified,
+            Sampler::Halton => sampler::Sampler::Halton,
+            Sampler::Sobol => sampler::Sampler::Sobol,
+        }
+    }
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         image_width: u32,
         aspect_ratio: f64,
@@ -43,24 +357,218 @@ impl Camera {
             samples_per_pixel,
             max_depth,
             background,
+            seed: 0,
+            sampler: Sampler::default(),
+            filter: Filter::default(),
+            adaptive: None,
+            tile_size: default_tile_size(),
+            tile_order: TileOrder::default(),
+            aovs: Vec::new(),
+            exposure: 0.0,
+            white_balance: None,
+            tone_map: ToneMap::default(),
+            spectral: false,
+        }
+    }
+
+    pub fn with_image_width(self, image_width: u32) -> Self {
+        Camera {
+            image_width,
+            ..self
+        }
+    }
+
+    pub fn with_samples_per_pixel(self, samples_per_pixel: usize) -> Self {
+        Camera {
+            samples_per_pixel,
+            ..self
+        }
+    }
+
+    pub fn with_max_depth(self, max_depth: u32) -> Self {
+        Camera { max_depth, ..self }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Camera { seed, ..self }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn with_sampler(self, sampler: Sampler) -> Self {
+        Camera { sampler, ..self }
+    }
+
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Camera { filter, ..self }
+    }
+
+    pub fn with_adaptive(self, adaptive: Adaptive) -> Self {
+        Camera {
+            adaptive: Some(adaptive),
+            ..self
+        }
+    }
+
+    /// Samples adaptively down to `threshold`, keeping the rest of the scene's adaptive settings
+    /// or, if it has none, taking batches of 16, or of `samples_per_pixel` if that's fewer, up to
+    /// 16 times the average in any one pixel.
+    pub fn with_adaptive_threshold(self, threshold: f64) -> Self {
+        let adaptive = match self.adaptive {
+            Some(adaptive) => Adaptive {
+                threshold,
+                ..adaptive
+            },
+            None => Adaptive::new(
+                threshold,
+                default_min_samples().min(self.samples_per_pixel),
+                16 * self.samples_per_pixel,
+            ),
+        };
+        self.with_adaptive(adaptive)
+    }
+
+    pub fn with_tiles(self, tile_size: u32, tile_order: TileOrder) -> Self {
+        Camera {
+            tile_size,
+            tile_order,
+            ..self
+        }
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    pub fn with_aovs(self, aovs: Vec<Aov>) -> Self {
+        Camera { aovs, ..self }
+    }
+
+    pub fn with_exposure(self, exposure: f64) -> Self {
+        Camera { exposure, ..self }
+    }
+
+    pub fn with_white_balance(self, white_balance: f64) -> Self {
+        Camera {
+            white_balance: Some(white_balance),
+            ..self
+        }
+    }
+
+    pub fn with_tone_map(self, tone_map: ToneMap) -> Self {
+        Camera { tone_map, ..self }
+    }
+
+    pub fn with_spectral(self, spectral: bool) -> Self {
+        Camera { spectral, ..self }
+    }
+
+    pub(super) fn validate(&self, path: &str, v: &mut Validator) {
+        let field = |name: &str| format!("{}.{}", path, name);
+        if self.image_width == 0 {
+            v.report(&field("image_width"), "must be positive");
+        }
+        if self.aspect_ratio <= 0.0 {
+            v.report(
+                &field("aspect_ratio"),
+                format!("must be positive, got {}", self.aspect_ratio),
+            );
+        }
+        let view = &self.look_at - &self.look_from;
+        if view.near_zero() {
+            v.report(
+                &field("look_at"),
+                "look_from and look_at are the same point, so there is no view direction",
+            );
+        } else if self.v_up.cross(&view).near_zero() {
+            v.report(&field("v_up"), "must not be parallel to the view direction");
+        }
+        if self.v_fov <= 0.0 || self.v_fov >= 180.0 {
+            v.report(
+                &field("v_fov"),
+                format!("must be between 0 and 180 degrees, got {}", self.v_fov),
+            );
+        }
+        if self.defocus_angle < 0.0 {
+            v.report(
+                &field("defocus_angle"),
+                format!("must not be negative, got {}", self.defocus_angle),
+            );
+        }
+        if self.focus_dist <= 0.0 {
+            v.report(
+                &field("focus_dist"),
+                format!("must be positive, got {}", self.focus_dist),
+            );
+        }
+        if self.samples_per_pixel == 0 {
+            v.report(&field("samples_per_pixel"), "must be positive");
+        }
+        self.filter.validate(&field("filter"), v);
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.validate(&field("adaptive"), v);
+            if self.samples_per_pixel < adaptive.min_samples {
+                v.report(
+                    &field("samples_per_pixel"),
+                    format!(
+                        "must be at least adaptive.min_samples ({}) to give every pixel its \
+                         first batch, got {}",
+                        adaptive.min_samples, self.samples_per_pixel
+                    ),
+                );
+            }
+        }
+        if self.tile_size == 0 {
+            v.report(&field("tile_size"), "must be positive");
+        }
+        if !self.exposure.is_finite() {
+            v.report(
+                &field("exposure"),
+                format!("must be a number of stops, got {}", self.exposure),
+            );
+        }
+        if let Some(kelvin) = self.white_balance {
+            if !(1667.0..=25000.0).contains(&kelvin) {
+                v.report(
+                    &field("white_balance"),
+                    format!("must be between 1667 and 25000 K, got {}", kelvin),
+                );
+            }
         }
     }
 }
 
-impl Into<camera::Camera> for Camera {
-    fn into(self) -> camera::Camera {
-        camera::Camera::new(
-            self.image_width,
-            self.aspect_ratio,
-            self.look_from,
-            self.look_at,
-            self.v_up,
-            self.v_fov,
-            self.defocus_angle,
-            self.focus_dist,
-            self.samples_per_pixel,
-            self.max_depth,
-            self.background,
+impl From<Camera> for camera::Camera {
+    fn from(value: Camera) -> Self {
+        let camera = camera::Camera::new(
+            value.image_width,
+            value.aspect_ratio,
+            value.look_from,
+            value.look_at,
+            value.v_up,
+            value.v_fov,
+            value.defocus_angle,
+            value.focus_dist,
+            value.samples_per_pixel,
+            value.max_depth,
+            value.background,
         )
+        .with_seed(value.seed)
+        .with_sampler(value.sampler.into())
+        .with_filter(value.filter.into())
+        .with_tiles(value.tile_size, value.tile_order.into())
+        .with_aovs(value.aovs.into_iter().map(Into::into).collect())
+        .with_spectral(value.spectral)
+        .with_tone_map(tonemap::ToneMap::new(
+            value.exposure,
+            value.white_balance,
+            value.tone_map.into(),
+        ));
+        match value.adaptive {
+            Some(adaptive) => camera.with_adaptive(adaptive.into()),
+            None => camera,
+        }
     }
 }
@@ -0,0 +1,101 @@
+use std::{fmt, io};
+
+use crate::hittable::SingularMatrix;
+
+use super::Diagnostic;
+
+/// Everything that can go wrong turning a scene file into something renderable.
+#[derive(Debug)]
+pub enum SceneError {
+    /// The scene file itself could not be read.
+    Io { path: String, source: io::Error },
+    /// The scene file is not valid JSON or does not match the scene format.
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// An image texture could not be opened or decoded.
+    Texture {
+        path: String,
+        source: image::ImageError,
+    },
+    /// A mesh file could not be opened or parsed.
+    Mesh { path: String, source: io::Error },
+    /// An `Instance` refers to a definition that does not exist or that contains itself.
+    Definition(String),
+    /// A transform has no inverse. `validate` reports these first, with their paths.
+    Transform(SingularMatrix),
+    /// The scene parsed but `validate` found problems with it.
+    Invalid(Vec<Diagnostic>),
+}
+
+impl SceneError {
+    pub(super) fn from_json(path: &str, e: serde_json::Error) -> SceneError {
+        if e.is_io() {
+            return SceneError::Io {
+                path: path.to_owned(),
+                source: e.into(),
+            };
+        }
+        // serde_json appends the position to its messages; it is reported separately here
+        let message = e.to_string();
+        let message = message
+            .rsplit_once(" at line ")
+            .map_or(message.as_str(), |(m, _)| m)
+            .to_owned();
+        SceneError::Parse {
+            path: path.to_owned(),
+            line: e.line(),
+            column: e.column(),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "could not read {}: {}", path, source),
+            SceneError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            SceneError::Texture { path, source } => {
+                write!(f, "could not load texture {}: {}", path, source)
+            }
+            SceneError::Mesh { path, source } => {
+                write!(f, "could not load mesh {}: {}", path, source)
+            }
+            SceneError::Definition(msg) => write!(f, "{}", msg),
+            SceneError::Transform(e) => write!(f, "{}", e),
+            SceneError::Invalid(diagnostics) => {
+                write!(f, "the scene has {} problem(s):", diagnostics.len())?;
+                for d in diagnostics {
+                    write!(f, "\n  {}", d)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } | SceneError::Mesh { source, .. } => Some(source),
+            SceneError::Texture { source, .. } => Some(source),
+            SceneError::Transform(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<SingularMatrix> for SceneError {
+    fn from(value: SingularMatrix) -> Self {
+        SceneError::Transform(value)
+    }
+}
@@ -1,22 +1,94 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{hittable, point3::Point3, vector3::Vector3};
+use crate::{hittable, matrix::Matrix4, point3::Point3, vector3::Vector3};
 
-use super::{material::Material, texture::Texture};
+use super::{
+    material::Material,
+    texture::{Seeds, Texture},
+    validate::Validator,
+    SceneError,
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Geometry {
     Sphere(Sphere),
     MovingSphere(MovingSphere),
     Quad(Quad),
+    Triangle(Triangle),
+    Mesh(Mesh),
     RectPrism(RectPrism),
     ConstantMedium(ConstantMedium),
     Translate(Translate),
-    RotateY(RotateY),
+    RotateX(AxisRotate),
+    RotateY(AxisRotate),
+    RotateZ(AxisRotate),
+    Rotate(Rotate),
+    Scale(Scale),
+    Matrix(Matrix),
+    Instance(Instance),
     List(Vec<Geometry>),
-    Bvh(Vec<Geometry>),
+    Bvh(Bvh),
+}
+
+/// Accepts either a bare list of objects or `{ "objects": [...], "split": "Median" }`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "BvhDef")]
+pub struct Bvh {
+    objects: Vec<Geometry>,
+    split: BvhSplit,
+    /// Whether the scene file used the bare list form, which changes the JSON path of the objects.
+    #[serde(skip)]
+    bare: bool,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum BvhSplit {
+    #[default]
+    Sah,
+    Median,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BvhDef {
+    List(Vec<Geometry>),
+    Config {
+        objects: Vec<Geometry>,
+        #[serde(default)]
+        split: BvhSplit,
+    },
+}
+
+impl From<BvhDef> for Bvh {
+    fn from(value: BvhDef) -> Self {
+        match value {
+            BvhDef::List(objects) => Bvh {
+                objects,
+                split: BvhSplit::default(),
+                bare: true,
+            },
+            BvhDef::Config { objects, split } => Bvh {
+                objects,
+                split,
+                bare: false,
+            },
+        }
+    }
+}
+
+impl From<BvhSplit> for hittable::BvhSplit {
+    fn from(value: BvhSplit) -> Self {
+        match value {
+            BvhSplit::Sah => hittable::BvhSplit::Sah,
+            BvhSplit::Median => hittable::BvhSplit::Median,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -26,11 +98,65 @@ pub struct Translate {
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct RotateY {
+pub struct AxisRotate {
+    object: Box<Geometry>,
+    angle: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rotate {
     object: Box<Geometry>,
+    axis: Vector3,
     angle: f64,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scale {
+    object: Box<Geometry>,
+    scale: Vector3,
+}
+
+/// A raw row-major affine matrix applied to `object`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Matrix {
+    object: Box<Geometry>,
+    matrix: [[f64; 4]; 4],
+}
+
+/// Places the scene definition named `ref`. `transform` is applied in order, first to last.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Instance {
+    #[serde(rename = "ref")]
+    reference: String,
+    #[serde(default)]
+    transform: Vec<Transform>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Transform {
+    Translate(Vector3),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate { axis: Vector3, angle: f64 },
+    Scale(Vector3),
+    Matrix([[f64; 4]; 4]),
+}
+
+impl Transform {
+    fn matrix(&self) -> Matrix4 {
+        match self {
+            Transform::Translate(offset) => Matrix4::translation(offset),
+            Transform::RotateX(angle) => Matrix4::rotation_x(*angle),
+            Transform::RotateY(angle) => Matrix4::rotation_y(*angle),
+            Transform::RotateZ(angle) => Matrix4::rotation_z(*angle),
+            Transform::Rotate { axis, angle } => Matrix4::rotation(axis, *angle),
+            Transform::Scale(scale) => Matrix4::scale(scale),
+            Transform::Matrix(m) => Matrix4::new(*m),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RectPrism {
     a: Point3,
@@ -68,6 +194,20 @@ pub struct Quad {
     material: Material,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Triangle {
+    a: Point3,
+    b: Point3,
+    c: Point3,
+    material: Material,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Mesh {
+    path: String,
+    material: Material,
+}
+
 impl Geometry {
     pub fn new_sphere(center: Point3, radius: f64, material: Material) -> Self {
         Geometry::Sphere(Sphere {
@@ -95,6 +235,17 @@ impl Geometry {
         Geometry::Quad(Quad { q, u, v, material })
     }
 
+    pub fn new_triangle(a: Point3, b: Point3, c: Point3, material: Material) -> Self {
+        Geometry::Triangle(Triangle { a, b, c, material })
+    }
+
+    pub fn new_mesh(path: &str, material: Material) -> Self {
+        Geometry::Mesh(Mesh {
+            path: path.to_owned(),
+            material,
+        })
+    }
+
     pub fn new_box(a: Point3, b: Point3, material: Material) -> Self {
         Geometry::RectPrism(RectPrism { a, b, material })
     }
@@ -114,54 +265,432 @@ impl Geometry {
         })
     }
 
+    pub fn new_rotate_x(object: Geometry, angle: f64) -> Self {
+        Geometry::RotateX(AxisRotate {
+            object: Box::new(object),
+            angle,
+        })
+    }
+
     pub fn new_rotate_y(object: Geometry, angle: f64) -> Self {
-        Geometry::RotateY(RotateY {
+        Geometry::RotateY(AxisRotate {
+            object: Box::new(object),
+            angle,
+        })
+    }
+
+    pub fn new_rotate_z(object: Geometry, angle: f64) -> Self {
+        Geometry::RotateZ(AxisRotate {
             object: Box::new(object),
             angle,
         })
     }
 
+    pub fn new_rotate(object: Geometry, axis: Vector3, angle: f64) -> Self {
+        Geometry::Rotate(Rotate {
+            object: Box::new(object),
+            axis,
+            angle,
+        })
+    }
+
+    pub fn new_scale(object: Geometry, scale: Vector3) -> Self {
+        Geometry::Scale(Scale {
+            object: Box::new(object),
+            scale,
+        })
+    }
+
+    pub fn new_matrix(object: Geometry, matrix: [[f64; 4]; 4]) -> Self {
+        Geometry::Matrix(Matrix {
+            object: Box::new(object),
+            matrix,
+        })
+    }
+
+    pub fn new_instance(reference: &str, transform: Vec<Transform>) -> Self {
+        Geometry::Instance(Instance {
+            reference: reference.to_owned(),
+            transform,
+        })
+    }
+
     pub fn new_list(list: Vec<Geometry>) -> Self {
         Geometry::List(list)
     }
 
     pub fn new_bvh(list: Vec<Geometry>) -> Self {
-        Geometry::Bvh(list)
+        Geometry::new_bvh_with_split(list, BvhSplit::default())
+    }
+
+    pub fn new_bvh_with_split(list: Vec<Geometry>, split: BvhSplit) -> Self {
+        Geometry::Bvh(Bvh {
+            objects: list,
+            split,
+            bare: false,
+        })
     }
 }
 
-impl Into<hittable::Hittable> for Geometry {
-    fn into(self) -> hittable::Hittable {
-        match self {
-            Geometry::Sphere(s) => {
-                hittable::Sphere::new(s.center, s.radius, Arc::new(s.material.into()))
-            }
+/// Named geometry shared between `Instance`s. Each definition is built at most once, on first
+/// use, and every instance of it holds the same `Arc`.
+pub(super) struct Definitions {
+    geometry: BTreeMap<String, Geometry>,
+    built: HashMap<String, Arc<hittable::Hittable>>,
+    building: Vec<String>,
+    /// For the textures of everything built.
+    seeds: Seeds,
+    /// The ID for the next object built.
+    next_object: u32,
+}
+
+impl Definitions {
+    pub(super) fn new(geometry: BTreeMap<String, Geometry>, seeds: Seeds) -> Self {
+        Definitions {
+            geometry,
+            built: HashMap::new(),
+            building: vec![],
+            seeds,
+            next_object: 0,
+        }
+    }
+
+    fn resolve(&mut self, name: &str) -> Result<Arc<hittable::Hittable>, SceneError> {
+        if let Some(h) = self.built.get(name) {
+            return Ok(Arc::clone(h));
+        }
+        if self.building.iter().any(|n| n == name) {
+            return Err(SceneError::Definition(format!(
+                "definition '{}' contains an instance of itself",
+                name
+            )));
+        }
+        let geometry = self
+            .geometry
+            .remove(name)
+            .ok_or_else(|| SceneError::Definition(format!("no definition named '{}'", name)))?;
+
+        self.building.push(name.to_owned());
+        let h = Arc::new(geometry.build(self)?);
+        self.building.pop();
+        self.built.insert(name.to_owned(), Arc::clone(&h));
+        Ok(h)
+    }
+}
+
+impl Geometry {
+    pub(super) fn build(self, defs: &mut Definitions) -> Result<hittable::Hittable, SceneError> {
+        // shapes, meshes, boxes, media and instances are objects with IDs of their own; lists
+        // and transforms only group or move them
+        let is_object = matches!(
+            self,
+            Geometry::Sphere(_)
+                | Geometry::MovingSphere(_)
+                | Geometry::Quad(_)
+                | Geometry::Triangle(_)
+                | Geometry::Mesh(_)
+                | Geometry::RectPrism(_)
+                | Geometry::ConstantMedium(_)
+                | Geometry::Instance(_)
+        );
+        let built = match self {
+            Geometry::Sphere(s) => hittable::Sphere::new(
+                s.center,
+                s.radius,
+                Arc::new(s.material.build(&mut defs.seeds)?),
+            ),
             Geometry::MovingSphere(ms) => hittable::Sphere::new_moving(
                 ms.center1,
                 ms.center2,
                 ms.radius,
-                Arc::new(ms.material.into()),
+                Arc::new(ms.material.build(&mut defs.seeds)?),
             ),
-            Geometry::Quad(q) => hittable::Quad::new(q.q, q.u, q.v, Arc::new(q.material.into())),
+            Geometry::Quad(q) => {
+                hittable::Quad::new(q.q, q.u, q.v, Arc::new(q.material.build(&mut defs.seeds)?))
+            }
+            Geometry::Triangle(t) => {
+                hittable::Triangle::new(t.a, t.b, t.c, Arc::new(t.material.build(&mut defs.seeds)?))
+            }
+            Geometry::Mesh(m) => {
+                let material = Arc::new(m.material.build(&mut defs.seeds)?);
+                hittable::mesh::load_obj(&m.path, material).map_err(|source| SceneError::Mesh {
+                    path: m.path.clone(),
+                    source,
+                })?
+            }
             Geometry::RectPrism(r) => {
-                hittable::Quad::make_box(&r.a, &r.b, Arc::new(r.material.into()))
+                hittable::Quad::make_box(&r.a, &r.b, Arc::new(r.material.build(&mut defs.seeds)?))
             }
             Geometry::ConstantMedium(cm) => hittable::ConstantMedium::new(
-                Box::new((*cm.boundary).into()),
+                Box::new(cm.boundary.build(defs)?),
                 cm.density,
-                Arc::new(cm.phase_func.into()),
+                Arc::new(cm.phase_func.build(&mut defs.seeds)?),
             ),
             Geometry::Translate(t) => {
-                hittable::Translate::new(Box::new((*t.object).into()), t.offset)
+                hittable::Translate::new(Box::new(t.object.build(defs)?), t.offset)
+            }
+            Geometry::RotateX(r) => hittable::Transform::new(
+                Arc::new(r.object.build(defs)?),
+                Matrix4::rotation_x(r.angle),
+            )?,
+            Geometry::RotateY(r) => {
+                hittable::RotateY::new(Box::new(r.object.build(defs)?), r.angle)
+            }
+            Geometry::RotateZ(r) => hittable::Transform::new(
+                Arc::new(r.object.build(defs)?),
+                Matrix4::rotation_z(r.angle),
+            )?,
+            Geometry::Rotate(r) => hittable::Transform::new(
+                Arc::new(r.object.build(defs)?),
+                Matrix4::rotation(&r.axis, r.angle),
+            )?,
+            Geometry::Scale(s) => {
+                hittable::Transform::new(Arc::new(s.object.build(defs)?), Matrix4::scale(&s.scale))?
+            }
+            Geometry::Matrix(m) => {
+                hittable::Transform::new(Arc::new(m.object.build(defs)?), Matrix4::new(m.matrix))?
+            }
+            Geometry::Instance(i) => {
+                hittable::Transform::new(defs.resolve(&i.reference)?, i.matrix())?
+            }
+            Geometry::List(l) => hittable::HittableList::new(
+                l.into_iter()
+                    .map(|h| h.build(defs))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Geometry::Bvh(b) => hittable::BvhNode::new_with_split(
+                b.objects
+                    .into_iter()
+                    .map(|h| h.build(defs))
+                    .collect::<Result<_, _>>()?,
+                b.split.into(),
+            ),
+        };
+        if !is_object {
+            return Ok(built);
+        }
+        let object_id = defs.next_object;
+        defs.next_object += 1;
+        Ok(built.with_object(object_id))
+    }
+
+    /// The variant name, as it appears in the scene file.
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            Geometry::Sphere(_) => "Sphere",
+            Geometry::MovingSphere(_) => "MovingSphere",
+            Geometry::Quad(_) => "Quad",
+            Geometry::Triangle(_) => "Triangle",
+            Geometry::Mesh(_) => "Mesh",
+            Geometry::RectPrism(_) => "RectPrism",
+            Geometry::ConstantMedium(_) => "ConstantMedium",
+            Geometry::Translate(_) => "Translate",
+            Geometry::RotateX(_) => "RotateX",
+            Geometry::RotateY(_) => "RotateY",
+            Geometry::RotateZ(_) => "RotateZ",
+            Geometry::Rotate(_) => "Rotate",
+            Geometry::Scale(_) => "Scale",
+            Geometry::Matrix(_) => "Matrix",
+            Geometry::Instance(_) => "Instance",
+            Geometry::List(_) => "List",
+            Geometry::Bvh(_) => "Bvh",
+        }
+    }
+
+    /// Whether the built object can be sampled by `Hittable::random` and `pdf_value`.
+    /// `visiting` holds the definitions being looked into, so cyclic ones end the search.
+    pub(super) fn can_sample<'a>(
+        &'a self,
+        definitions: &'a BTreeMap<String, Geometry>,
+        visiting: &mut Vec<&'a str>,
+    ) -> bool {
+        match self {
+            Geometry::Sphere(_)
+            | Geometry::MovingSphere(_)
+            | Geometry::Quad(_)
+            | Geometry::Triangle(_)
+            | Geometry::RectPrism(_) => true,
+            Geometry::Translate(Translate { object, .. })
+            | Geometry::RotateX(AxisRotate { object, .. })
+            | Geometry::RotateY(AxisRotate { object, .. })
+            | Geometry::RotateZ(AxisRotate { object, .. })
+            | Geometry::Rotate(Rotate { object, .. })
+            | Geometry::Scale(Scale { object, .. })
+            | Geometry::Matrix(Matrix { object, .. }) => object.can_sample(definitions, visiting),
+            Geometry::Instance(i) => {
+                if visiting.contains(&i.reference.as_str()) {
+                    return false;
+                }
+                let Some(definition) = definitions.get(&i.reference) else {
+                    return false;
+                };
+                visiting.push(&i.reference);
+                let can_sample = definition.can_sample(definitions, visiting);
+                visiting.pop();
+                can_sample
+            }
+            Geometry::List(l) => {
+                !l.is_empty() && l.iter().all(|g| g.can_sample(definitions, visiting))
+            }
+            _ => false,
+        }
+    }
+
+    /// Appends the names of all definitions instanced directly or indirectly by this geometry,
+    /// without following the definitions themselves.
+    pub(super) fn references<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Geometry::Instance(i) => out.push(&i.reference),
+            Geometry::ConstantMedium(cm) => cm.boundary.references(out),
+            Geometry::Translate(Translate { object, .. })
+            | Geometry::RotateX(AxisRotate { object, .. })
+            | Geometry::RotateY(AxisRotate { object, .. })
+            | Geometry::RotateZ(AxisRotate { object, .. })
+            | Geometry::Rotate(Rotate { object, .. })
+            | Geometry::Scale(Scale { object, .. })
+            | Geometry::Matrix(Matrix { object, .. }) => object.references(out),
+            Geometry::List(l) => l.iter().for_each(|g| g.references(out)),
+            Geometry::Bvh(b) => b.objects.iter().for_each(|g| g.references(out)),
+            _ => {}
+        }
+    }
+
+    pub(super) fn validate(&self, path: &str, v: &mut Validator) {
+        let path = format!("{}.{}", path, self.kind());
+        match self {
+            Geometry::Sphere(s) => {
+                check_radius(s.radius, &path, v);
+                s.material.validate(&format!("{}.material", path), v);
+            }
+            Geometry::MovingSphere(ms) => {
+                check_radius(ms.radius, &path, v);
+                ms.material.validate(&format!("{}.material", path), v);
+            }
+            Geometry::Quad(q) => {
+                if q.u.cross(&q.v).near_zero() {
+                    v.report(
+                        &path,
+                        "u and v are parallel or zero, so the quad has no area",
+                    );
+                }
+                q.material.validate(&format!("{}.material", path), v);
+            }
+            Geometry::Triangle(t) => {
+                if (&t.b - &t.a).cross(&(&t.c - &t.a)).near_zero() {
+                    v.report(
+                        &path,
+                        "the corners are collinear, so the triangle has no area",
+                    );
+                }
+                t.material.validate(&format!("{}.material", path), v);
+            }
+            Geometry::Mesh(m) => {
+                if !Path::new(&m.path).is_file() {
+                    v.report(&format!("{}.path", path), format!("{} not found", m.path));
+                }
+                m.material.validate(&format!("{}.material", path), v);
+            }
+            Geometry::RectPrism(r) => {
+                let d = &r.b - &r.a;
+                if d.x == 0.0 || d.y == 0.0 || d.z == 0.0 {
+                    v.report(
+                        &path,
+                        "a and b share a coordinate, so the box has no volume",
+                    );
+                }
+                r.material.validate(&format!("{}.material", path), v);
+            }
+            Geometry::ConstantMedium(cm) => {
+                if cm.density <= 0.0 {
+                    v.report(
+                        &format!("{}.density", path),
+                        format!("must be positive, got {}", cm.density),
+                    );
+                }
+                cm.boundary.validate(&format!("{}.boundary", path), v);
+                cm.phase_func.validate(&format!("{}.phase_func", path), v);
+            }
+            Geometry::Translate(Translate { object, .. })
+            | Geometry::RotateX(AxisRotate { object, .. })
+            | Geometry::RotateY(AxisRotate { object, .. })
+            | Geometry::RotateZ(AxisRotate { object, .. }) => {
+                object.validate(&format!("{}.object", path), v);
+            }
+            Geometry::Rotate(r) => {
+                if r.axis.near_zero() {
+                    v.report(&format!("{}.axis", path), "the rotation axis is zero");
+                }
+                r.object.validate(&format!("{}.object", path), v);
+            }
+            Geometry::Scale(s) => {
+                check_invertible(&Matrix4::scale(&s.scale), &format!("{}.scale", path), v);
+                s.object.validate(&format!("{}.object", path), v);
+            }
+            Geometry::Matrix(m) => {
+                check_invertible(&Matrix4::new(m.matrix), &format!("{}.matrix", path), v);
+                m.object.validate(&format!("{}.object", path), v);
+            }
+            Geometry::Instance(i) => {
+                if !v.has_definition(&i.reference) {
+                    v.report(
+                        &format!("{}.ref", path),
+                        format!("there is no definition named '{}'", i.reference),
+                    );
+                }
+                for (n, t) in i.transform.iter().enumerate() {
+                    if let Transform::Rotate { axis, .. } = t {
+                        if axis.near_zero() {
+                            v.report(
+                                &format!("{}.transform[{}]", path, n),
+                                "the rotation axis is zero",
+                            );
+                            continue;
+                        }
+                    }
+                    check_invertible(&t.matrix(), &format!("{}.transform[{}]", path, n), v);
+                }
             }
-            Geometry::RotateY(r) => hittable::RotateY::new(Box::new((*r.object).into()), r.angle),
             Geometry::List(l) => {
-                hittable::HittableList::new(l.into_iter().map(|h| h.into()).collect())
+                for (n, g) in l.iter().enumerate() {
+                    g.validate(&format!("{}[{}]", path, n), v);
+                }
             }
             Geometry::Bvh(b) => {
-                let l = b.len();
-                hittable::BvhNode::new(&b.into_iter().map(|h| h.into()).collect(), 0, l)
+                let objects = if b.bare {
+                    path
+                } else {
+                    format!("{}.objects", path)
+                };
+                for (n, g) in b.objects.iter().enumerate() {
+                    g.validate(&format!("{}[{}]", objects, n), v);
+                }
             }
         }
     }
 }
+
+impl Instance {
+    fn matrix(&self) -> Matrix4 {
+        self.transform
+            .iter()
+            .fold(Matrix4::identity(), |acc, t| t.matrix() * acc)
+    }
+}
+
+fn check_radius(radius: f64, path: &str, v: &mut Validator) {
+    if radius <= 0.0 {
+        v.report(
+            &format!("{}.radius", path),
+            format!("must be positive, got {}", radius),
+        );
+    }
+}
+
+fn check_invertible(matrix: &Matrix4, path: &str, v: &mut Validator) {
+    if matrix.inverse().is_none() {
+        v.report(
+            path,
+            "the transform is singular, e.g. a scale with a zero component",
+        );
+    }
+}
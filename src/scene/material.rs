@@ -1,51 +1,476 @@
 use std::sync::Arc;
 
-use crate::{color::Color, material, scene::texture::Texture};
+use crate::{
+    color::Color,
+    material,
+    point3::Point3,
+    scene::texture::{Param, Rgb, Scalar, Seeds, Texture},
+    spectrum,
+};
 use serde::{Deserialize, Serialize};
 
+use super::{validate::Validator, SceneError};
+
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Material {
-    Lambertian(Texture),
-    Metal { color: Color, fuzz: f64 },
-    Dielectric(f64),
-    Isotropic(Texture),
-    DiffuseLight(Texture),
+    Lambertian(Rgb),
+    Metal {
+        color: Rgb,
+        fuzz: Scalar,
+    },
+    /// A metal by its measured optical constants, with roughness and anisotropy from 0 to 1.
+    Conductor {
+        ior: Ior,
+        #[serde(default)]
+        roughness: Scalar,
+        #[serde(default)]
+        anisotropy: Scalar,
+    },
+    /// Clear glass, by the same index of refraction as `Glass`.
+    Dielectric(RefractiveIndex),
+    /// Solid glass with roughness from 0 to 1, tinted by absorption inside it.
+    Glass {
+        ior: RefractiveIndex,
+        #[serde(default)]
+        roughness: Scalar,
+        #[serde(default)]
+        absorption: Option<Absorption>,
+    },
+    /// A thin pane of glass, such as a window, that lets `tint` through head on.
+    ThinGlass {
+        ior: RefractiveIndex,
+        #[serde(default = "white")]
+        tint: Rgb,
+    },
+    /// A Disney-style uber material; see `material::Principled`.
+    Principled {
+        base_color: Rgb,
+        #[serde(default)]
+        metallic: Scalar,
+        #[serde(default = "half")]
+        roughness: Scalar,
+        #[serde(default = "half")]
+        specular: Scalar,
+        #[serde(default)]
+        sheen: Scalar,
+        #[serde(default)]
+        clearcoat: Scalar,
+        #[serde(default = "glossy")]
+        clearcoat_roughness: Scalar,
+        #[serde(default)]
+        transmission: Scalar,
+        #[serde(default)]
+        emission: Option<Rgb>,
+    },
+    Isotropic(Rgb),
+    DiffuseLight(Rgb),
 }
 
 impl Material {
     pub fn new_lambertian(t: Texture) -> Self {
-        Material::Lambertian(t)
+        Material::Lambertian(Param::Texture(t))
     }
 
     pub fn new_lamb_solid(color: Color) -> Self {
-        Material::Lambertian(Texture::new_solid(color))
+        Material::Lambertian(Param::Constant(color))
     }
 
     pub fn new_metal(color: Color, fuzz: f64) -> Self {
-        Material::Metal { color, fuzz }
+        Material::Metal {
+            color: Param::Constant(color),
+            fuzz: Param::Constant(fuzz),
+        }
+    }
+
+    pub fn new_conductor(ior: Ior, roughness: f64, anisotropy: f64) -> Self {
+        Material::Conductor {
+            ior,
+            roughness: Param::Constant(roughness),
+            anisotropy: Param::Constant(anisotropy),
+        }
     }
 
     pub fn new_dielectric(ir: f64) -> Self {
-        Material::Dielectric(ir)
+        Material::Dielectric(RefractiveIndex::Constant(ir))
+    }
+
+    pub fn new_glass(ior: RefractiveIndex, roughness: f64, absorption: Option<Absorption>) -> Self {
+        Material::Glass {
+            ior,
+            roughness: Param::Constant(roughness),
+            absorption,
+        }
+    }
+
+    pub fn new_thin_glass(ior: f64, tint: Color) -> Self {
+        Material::ThinGlass {
+            ior: RefractiveIndex::Constant(ior),
+            tint: Param::Constant(tint),
+        }
+    }
+
+    /// A principled material of `base_color` with every other parameter at its default.
+    pub fn new_principled(base_color: Texture) -> Self {
+        Material::Principled {
+            base_color: Param::Texture(base_color),
+            metallic: Scalar::default(),
+            roughness: half(),
+            specular: half(),
+            sheen: Scalar::default(),
+            clearcoat: Scalar::default(),
+            clearcoat_roughness: glossy(),
+            transmission: Scalar::default(),
+            emission: None,
+        }
     }
 
     pub fn new_isotropic(t: Texture) -> Self {
-        Material::Isotropic(t)
+        Material::Isotropic(Param::Texture(t))
     }
 
     pub fn new_diffuse_light(t: Texture) -> Self {
-        Material::DiffuseLight(t)
+        Material::DiffuseLight(Param::Texture(t))
+    }
+
+    pub(super) fn validate(&self, path: &str, v: &mut Validator) {
+        match self {
+            Material::Lambertian(t) => t.validate(&format!("{}.Lambertian", path), v),
+            Material::Metal { color, fuzz } => {
+                let path = format!("{}.Metal", path);
+                color.validate(&format!("{}.color", path), v);
+                let path = format!("{}.fuzz", path);
+                fuzz.validate_by(&path, v, |fuzz, v| {
+                    if *fuzz < 0.0 {
+                        v.report(&path, format!("must not be negative, got {}", fuzz));
+                    }
+                });
+            }
+            Material::Conductor {
+                ior,
+                roughness,
+                anisotropy,
+            } => {
+                let path = format!("{}.Conductor", path);
+                roughness.validate(&format!("{}.roughness", path), v);
+                anisotropy.validate(&format!("{}.anisotropy", path), v);
+                if let Ior::Complex { eta, k } = ior {
+                    let path = format!("{}.ior.Complex", path);
+                    let negative = |c: &Rgb| matches!(c, Param::Constant(c) if c.r < 0.0 || c.g < 0.0 || c.b < 0.0);
+                    if negative(eta) || negative(k) {
+                        v.report(&path, "eta and k must not be negative");
+                    }
+                    eta.validate(&format!("{}.eta", path), v);
+                    k.validate(&format!("{}.k", path), v);
+                }
+            }
+            Material::Dielectric(ir) => ir.validate(&format!("{}.Dielectric", path), v),
+            Material::Glass {
+                ior,
+                roughness,
+                absorption,
+            } => {
+                let path = format!("{}.Glass", path);
+                ior.validate(&format!("{}.ior", path), v);
+                roughness.validate(&format!("{}.roughness", path), v);
+                if let Some(Absorption { color, distance }) = absorption {
+                    let color_path = format!("{}.absorption.color", path);
+                    color.validate_by(&color_path, v, |color, v| {
+                        for c in [color.r, color.g, color.b] {
+                            if c <= 0.0 || c > 1.0 {
+                                v.report(
+                                    &color_path,
+                                    format!(
+                                        "each channel must be above 0 and at most 1, got {}",
+                                        c
+                                    ),
+                                );
+                                break;
+                            }
+                        }
+                    });
+                    if *distance <= 0.0 {
+                        v.report(
+                            &format!("{}.absorption.distance", path),
+                            format!("must be positive, got {}", distance),
+                        );
+                    }
+                }
+            }
+            Material::ThinGlass { ior, tint } => {
+                let path = format!("{}.ThinGlass", path);
+                ior.validate(&format!("{}.ior", path), v);
+                let tint_path = format!("{}.tint", path);
+                tint.validate_by(&tint_path, v, |tint, v| {
+                    for c in [tint.r, tint.g, tint.b] {
+                        if !(0.0..=1.0).contains(&c) {
+                            v.report(
+                                &tint_path,
+                                format!("each channel must be between 0 and 1, got {}", c),
+                            );
+                            break;
+                        }
+                    }
+                });
+            }
+            Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                emission,
+            } => {
+                let path = format!("{}.Principled", path);
+                let field = |name: &str| format!("{}.{}", path, name);
+                base_color.validate(&field("base_color"), v);
+                for (name, scalar) in [
+                    ("metallic", metallic),
+                    ("roughness", roughness),
+                    ("specular", specular),
+                    ("sheen", sheen),
+                    ("clearcoat", clearcoat),
+                    ("clearcoat_roughness", clearcoat_roughness),
+                    ("transmission", transmission),
+                ] {
+                    scalar.validate(&field(name), v);
+                }
+                if let Some(emission) = emission {
+                    emission.validate(&field("emission"), v);
+                }
+            }
+            Material::Isotropic(t) => t.validate(&format!("{}.Isotropic", path), v),
+            Material::DiffuseLight(t) => t.validate(&format!("{}.DiffuseLight", path), v),
+        }
+    }
+}
+
+impl Material {
+    pub(super) fn build(self, seeds: &mut Seeds) -> Result<crate::material::Material, SceneError> {
+        Ok(match self {
+            Material::Lambertian(t) => material::Lambertian::new(Arc::new(t.build(seeds)?)),
+            Material::Metal { color, fuzz } => {
+                material::Metal::new(Arc::new(color.build(seeds)?), Arc::new(fuzz.build(seeds)?))
+            }
+            Material::Conductor {
+                ior,
+                roughness,
+                anisotropy,
+            } => material::Metal::new_conductor(
+                ior.build(seeds)?,
+                Arc::new(roughness.build(seeds)?),
+                Arc::new(anisotropy.build(seeds)?),
+            ),
+            Material::Dielectric(RefractiveIndex::Constant(ir)) => material::Dielectric::new(ir),
+            Material::Dielectric(ir) => material::Dielectric::new_glass(
+                ir.build(seeds)?,
+                Arc::new(Scalar::default().build(seeds)?),
+                None,
+            ),
+            Material::Glass {
+                ior,
+                roughness,
+                absorption,
+            } => {
+                let absorption = match absorption {
+                    Some(a) => Some(material::Absorption::new(
+                        Arc::new(a.color.build(seeds)?),
+                        a.distance,
+                    )),
+                    None => None,
+                };
+                material::Dielectric::new_glass(
+                    ior.build(seeds)?,
+                    Arc::new(roughness.build(seeds)?),
+                    absorption,
+                )
+            }
+            Material::ThinGlass { ior, tint } => {
+                material::ThinDielectric::new(ior.build(seeds)?, Arc::new(tint.build(seeds)?))
+            }
+            Material::Principled {
+                base_color,
+                metallic,
+                roughness,
+                specular,
+                sheen,
+                clearcoat,
+                clearcoat_roughness,
+                transmission,
+                emission,
+            } => material::Principled::new(material::PrincipledParams {
+                base_color: Arc::new(base_color.build(seeds)?),
+                metallic: Arc::new(metallic.build(seeds)?),
+                roughness: Arc::new(roughness.build(seeds)?),
+                specular: Arc::new(specular.build(seeds)?),
+                sheen: Arc::new(sheen.build(seeds)?),
+                clearcoat: Arc::new(clearcoat.build(seeds)?),
+                clearcoat_roughness: Arc::new(clearcoat_roughness.build(seeds)?),
+                transmission: Arc::new(transmission.build(seeds)?),
+                emission: emission.map(|e| e.build(seeds).map(Arc::new)).transpose()?,
+            }),
+            Material::Isotropic(t) => material::Isotropic::new(Arc::new(t.build(seeds)?)),
+            Material::DiffuseLight(t) => material::DiffuseLight::new(Arc::new(t.build(seeds)?)),
+        })
+    }
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn half() -> Scalar {
+    Scalar::Constant(0.5)
+}
+
+/// The default clear coat roughness, for a glossy lacquer.
+fn glossy() -> Scalar {
+    Scalar::Constant(0.1)
+}
+
+fn white() -> Rgb {
+    Param::Constant(Color::new(1.0, 1.0, 1.0))
+}
+
+fn validate_ior(path: &str, ior: f64, v: &mut Validator) {
+    if ior <= 0.0 {
+        v.report(path, format!("must be positive, got {}", ior));
+    }
+}
+
+/// The index of refraction of glass: a number, a formula for how it varies with the wavelength
+/// that spectral mode uses to split light into its colors, or a texture mapped to a range of
+/// indices at each point.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    Dispersive(Dispersion),
+    Textured(IorMap),
+}
+
+/// A texture whose luminance, from 0 to 1, gives indices of refraction from `min` to `max`.
+/// Textures such as images only hold values from 0 to 1, so they need a range to reach the
+/// indices of real glass.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IorMap {
+    pub texture: Texture,
+    #[serde(default = "one")]
+    pub min: f64,
+    pub max: f64,
+}
+
+/// The index of refraction as a function of the wavelength λ in micrometres.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Dispersion {
+    /// `a + b / λ²`.
+    Cauchy { a: f64, b: f64 },
+    /// `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, as glass catalogues give it.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl RefractiveIndex {
+    fn validate(&self, path: &str, v: &mut Validator) {
+        let dispersion = match self {
+            RefractiveIndex::Constant(n) => return validate_ior(path, *n, v),
+            RefractiveIndex::Textured(map) => return map.validate(path, v),
+            RefractiveIndex::Dispersive(d) => d,
+        };
+        let index = dispersion.clone().into_index();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let steps = 47;
+        for i in 0..=steps {
+            let nm = spectrum::MIN_WAVELENGTH
+                + (spectrum::MAX_WAVELENGTH - spectrum::MIN_WAVELENGTH) * i as f64 / steps as f64;
+            let n = index.at(nm, 0.0, 0.0, &origin);
+            // NaN where the Sellmeier equation has no real root
+            if !(n > 0.0 && n.is_finite()) {
+                v.report(
+                    path,
+                    format!(
+                        "must be positive across the visible range, got {} at {} nm",
+                        n, nm
+                    ),
+                );
+                return;
+            }
+        }
     }
 }
 
-impl Into<crate::material::Material> for Material {
-    fn into(self) -> crate::material::Material {
+impl IorMap {
+    fn validate(&self, path: &str, v: &mut Validator) {
+        self.texture.validate(&format!("{}.texture", path), v);
+        // any index the map gives is then at least 1, so light always has a way through
+        for (name, n) in [("min", self.min), ("max", self.max)] {
+            if !(n >= 1.0 && n.is_finite()) {
+                v.report(
+                    &format!("{}.{}", path, name),
+                    format!("must be at least 1, got {}", n),
+                );
+            }
+        }
+    }
+}
+
+impl Dispersion {
+    fn into_index(self) -> spectrum::RefractiveIndex {
         match self {
-            Material::Lambertian(t) => material::Lambertian::new(Arc::new(t.into())),
-            Material::Metal { color, fuzz } => material::Metal::new(color, fuzz),
-            Material::Dielectric(ir) => material::Dielectric::new(ir),
-            Material::Isotropic(t) => material::Isotropic::new(Arc::new(t.into())),
-            Material::DiffuseLight(t) => material::DiffuseLight::new(Arc::new(t.into())),
+            Dispersion::Cauchy { a, b } => spectrum::RefractiveIndex::Cauchy { a, b },
+            Dispersion::Sellmeier { b, c } => spectrum::RefractiveIndex::Sellmeier { b, c },
         }
     }
 }
+
+impl RefractiveIndex {
+    pub(super) fn build(self, seeds: &mut Seeds) -> Result<spectrum::RefractiveIndex, SceneError> {
+        Ok(match self {
+            RefractiveIndex::Constant(n) => spectrum::RefractiveIndex::Constant(n),
+            RefractiveIndex::Dispersive(d) => d.into_index(),
+            RefractiveIndex::Textured(IorMap { texture, min, max }) => {
+                spectrum::RefractiveIndex::Textured {
+                    texture: Arc::new(texture.build(seeds)?),
+                    min,
+                    max,
+                }
+            }
+        })
+    }
+}
+
+/// Beer–Lambert absorption, given as the `color` white light turns after travelling `distance`
+/// inside the medium. A textured color is read where a path enters the glass and tints the whole
+/// path inside it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Absorption {
+    pub color: Rgb,
+    pub distance: f64,
+}
+
+/// The complex index of refraction of a conductor, either measured for a common metal or given
+/// for red, green and blue.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Ior {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+    Complex { eta: Rgb, k: Rgb },
+}
+
+impl Ior {
+    pub(super) fn build(self, seeds: &mut Seeds) -> Result<material::Fresnel, SceneError> {
+        Ok(match self {
+            Ior::Gold => material::Fresnel::gold(),
+            Ior::Copper => material::Fresnel::copper(),
+            Ior::Aluminium => material::Fresnel::aluminium(),
+            Ior::Silver => material::Fresnel::silver(),
+            Ior::Complex { eta, k } => material::Fresnel::Conductor {
+                eta: Arc::new(eta.build(seeds)?),
+                k: Arc::new(k.build(seeds)?),
+            },
+        })
+    }
+}
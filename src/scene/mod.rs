@@ -1,20 +1,28 @@
 mod camera;
+mod error;
 mod geometry;
 mod material;
 mod texture;
+mod validate;
 
-use std::{fs::File, io::BufReader};
+use std::{collections::BTreeMap, fs::File, io::BufReader};
 
 use serde::{Deserialize, Serialize};
 
-use crate::hittable;
-pub use camera::Camera;
-pub use geometry::{ConstantMedium, Geometry, Quad, Sphere};
-pub use material::Material;
-pub use texture::Texture;
+use crate::{hittable, random};
+pub use camera::{Adaptive, Aov, Camera, Filter, Sampler, TileOrder, ToneMap};
+pub use error::SceneError;
+use geometry::Definitions;
+pub use geometry::{BvhSplit, ConstantMedium, Geometry, Quad, Sphere, Transform};
+pub use material::{Absorption, Dispersion, Ior, IorMap, Material, RefractiveIndex};
+use texture::Seeds;
+pub use texture::{Channel, Param, Rgb, Scalar, Texture};
+pub use validate::{validate, Diagnostic};
 
 #[derive(Serialize, Deserialize)]
 pub struct Scene {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    definitions: BTreeMap<String, Geometry>,
     world: Vec<Geometry>,
     lights: Vec<Geometry>,
     camera: Camera,
@@ -23,39 +31,416 @@ pub struct Scene {
 impl Scene {
     pub fn new(world: Vec<Geometry>, lights: Vec<Geometry>, camera: Camera) -> Self {
         Scene {
+            definitions: BTreeMap::new(),
             world,
             lights,
             camera,
         }
     }
+
+    /// Adds a named definition that `Geometry::Instance` can refer to.
+    pub fn with_definition(mut self, name: &str, geometry: Geometry) -> Self {
+        self.definitions.insert(name.to_owned(), geometry);
+        self
+    }
+
+    /// Identifies the objects of the scene, but not its camera, so that a saved render is only
+    /// resumed with the scene it was rendered from.
+    pub fn key(&self) -> u64 {
+        let json = serde_json::to_string(&(&self.definitions, &self.world, &self.lights))
+            .expect("scenes serialize to JSON");
+        random::hash_str(&json)
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn with_camera(self, camera: Camera) -> Self {
+        Scene { camera, ..self }
+    }
+
+    /// Validates the description and converts it into its world, its lights and camera.
+    pub fn build(self) -> Result<(hittable::Hittable, hittable::Hittable, Camera), SceneError> {
+        let diagnostics = validate(&self);
+        if !diagnostics.is_empty() {
+            return Err(SceneError::Invalid(diagnostics));
+        }
+
+        let mut defs = Definitions::new(self.definitions, Seeds::new(self.camera.seed()));
+        let world = self
+            .world
+            .into_iter()
+            .map(|obj| obj.build(&mut defs))
+            .collect::<Result<Vec<_>, _>>()?;
+        let lights = self
+            .lights
+            .into_iter()
+            .map(|obj| obj.build(&mut defs))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((single_or_list(world), single_or_list(lights), self.camera))
+    }
 }
 
+fn single_or_list(mut objects: Vec<hittable::Hittable>) -> hittable::Hittable {
+    if objects.len() == 1 {
+        objects.pop().unwrap()
+    } else {
+        hittable::HittableList::new(objects)
+    }
+}
+
+/// Parses a scene file without building or validating it.
+pub fn read(filename: &str) -> Result<Scene, SceneError> {
+    let file = File::open(filename).map_err(|source| SceneError::Io {
+        path: filename.to_owned(),
+        source,
+    })?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| SceneError::from_json(filename, e))
+}
+
+/// Reads a scene file into its world, its lights and the camera description. The camera is left
+/// as a `scene::Camera` so callers can override its settings before converting it.
 pub fn load(
     filename: &str,
-) -> (
-    hittable::Hittable,
-    hittable::Hittable,
-    crate::camera::Camera,
-) {
-    let scene: Scene = serde_json::from_reader(BufReader::new(
-        File::open(filename).expect(&format!("failed to open file {}", filename)),
-    ))
-    .expect("failed to deserialize");
-
-    let world: Vec<hittable::Hittable> = scene.world.into_iter().map(|obj| obj.into()).collect();
-    let lights: Vec<hittable::Hittable> = scene.lights.into_iter().map(|obj| obj.into()).collect();
-
-    let world = if world.len() == 1 {
-        world[0].clone()
-    } else {
-        hittable::HittableList::new(world)
+) -> Result<(hittable::Hittable, hittable::Hittable, Camera), SceneError> {
+    read(filename)?.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use crate::{
+        color::Color, interval::Interval, point3::Point3, random::SampleRng, ray::Ray,
+        vector3::Vector3,
     };
 
-    let lights = if lights.len() == 1 {
-        lights[0].clone()
-    } else {
-        hittable::HittableList::new(lights)
+    use super::{
+        validate, Adaptive, Camera, Geometry, Material, Param, Scene, SceneError, Seeds, Transform,
     };
 
-    (world, lights, scene.camera.into())
+    fn camera() -> Camera {
+        Camera::new(
+            100,
+            1.0,
+            Point3::new(0.0, 0.0, -5.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            5.0,
+            10,
+            10,
+            Color::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let e = serde_json::from_str::<Scene>("{\n  \"world\": [],\n  \"lights\": 3\n}")
+            .map_err(|e| SceneError::from_json("bad.json", e))
+            .err()
+            .unwrap();
+        match e {
+            SceneError::Parse { line, column, .. } => assert_eq!((line, column), (3, 13)),
+            e => panic!("expected a parse error, got {}", e),
+        }
+    }
+
+    #[test]
+    fn test_object_ids() {
+        let grey = Material::new_lamb_solid(Color::new(0.5, 0.5, 0.5));
+        let x = Vector3::new(1.0, 0.0, 0.0);
+        let z = Vector3::new(0.0, 0.0, 1.0);
+        let light = Geometry::new_quad(
+            Point3::new(-1.0, 5.0, -1.0),
+            &x * 2.0,
+            &z * 2.0,
+            grey.clone(),
+        );
+        // the whole world is one BVH, and the last two spheres are placements of one definition
+        let world = vec![Geometry::new_bvh(vec![
+            Geometry::new_sphere(Point3::new(-2.0, 0.0, 0.0), 0.5, grey.clone()),
+            Geometry::new_sphere(Point3::new(0.0, 0.0, 0.0), 0.5, grey.clone()),
+            Geometry::new_instance("ball", vec![Transform::Translate(&x * 2.0)]),
+            Geometry::new_instance("ball", vec![Transform::Translate(&x * 4.0)]),
+        ])];
+        let scene = Scene::new(world, vec![light], camera()).with_definition(
+            "ball",
+            Geometry::new_sphere(Point3::new(0.0, 0.0, 0.0), 0.5, grey),
+        );
+        let (world, _, _) = scene.build().unwrap();
+
+        let mut rng = SampleRng::seed_from_u64(0);
+        let ids: Vec<_> = [-2.0, 0.0, 2.0, 4.0]
+            .into_iter()
+            .map(|x| {
+                let r = Ray::new(Point3::new(x, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0), 0.0);
+                world
+                    .hit(&r, Interval::new(0.001, f64::MAX), &mut rng)
+                    .unwrap()
+                    .object
+            })
+            .collect();
+        for (i, a) in ids.iter().enumerate() {
+            assert!(
+                ids[i + 1..].iter().all(|b| a != b),
+                "repeated IDs {:?}",
+                ids
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let grey = Material::new_lamb_solid(Color::new(0.5, 0.5, 0.5));
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let x = Vector3::new(1.0, 0.0, 0.0);
+        let world = vec![
+            Geometry::new_sphere(origin.clone(), 1.0, grey.clone()),
+            Geometry::new_bvh(vec![
+                Geometry::new_sphere(origin.clone(), 0.0, grey.clone()),
+                Geometry::new_quad(origin.clone(), x.clone(), &x * 2.0, grey.clone()),
+            ]),
+            Geometry::new_instance("missing", vec![]),
+            Geometry::new_sphere(
+                origin.clone(),
+                1.0,
+                Material::new_metal(Color::new(0.5, 0.5, 0.5), -1.0),
+            ),
+        ];
+        let camera = Camera::new(
+            100,
+            1.0,
+            origin.clone(),
+            origin,
+            Vector3::new(0.0, 1.0, 0.0),
+            40.0,
+            0.0,
+            5.0,
+            10,
+            10,
+            Color::new(0.0, 0.0, 0.0),
+        );
+        let scene = Scene::new(world, vec![], camera);
+
+        let paths: Vec<_> = validate(&scene).into_iter().map(|d| d.path).collect();
+        assert_eq!(
+            paths,
+            [
+                "world[1].Bvh.objects[0].Sphere.radius",
+                "world[1].Bvh.objects[1].Quad",
+                "world[2].Instance.ref",
+                "world[3].Sphere.material.Metal.fuzz",
+                "lights",
+                "camera.look_at",
+            ]
+        );
+        assert!(matches!(scene.build(), Err(SceneError::Invalid(d)) if d.len() == 6));
+    }
+
+    #[test]
+    fn test_validate_adaptive() {
+        // an adaptive budget must cover every pixel's first batch
+        let adaptive = |camera: Camera| {
+            let scene = Scene::new(vec![], vec![], camera);
+            validate(&scene)
+                .into_iter()
+                .map(|d| d.path)
+                .filter(|p| p.starts_with("camera"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            adaptive(camera().with_adaptive(Adaptive::new(0.01, 16, 64))),
+            ["camera.samples_per_pixel"]
+        );
+        assert!(adaptive(camera().with_adaptive_threshold(0.01)).is_empty());
+    }
+
+    #[test]
+    fn test_scene_key() {
+        let grey = Material::new_lamb_solid(Color::new(0.5, 0.5, 0.5));
+        let sphere =
+            |radius| Geometry::new_sphere(Point3::new(0.0, 0.0, 0.0), radius, grey.clone());
+        let key = Scene::new(vec![sphere(1.0)], vec![], camera()).key();
+        // the camera's settings can change between a saved render and its resumption
+        let more_samples = camera().with_samples_per_pixel(100);
+        assert_eq!(
+            Scene::new(vec![sphere(1.0)], vec![], more_samples).key(),
+            key
+        );
+        assert_ne!(Scene::new(vec![sphere(2.0)], vec![], camera()).key(), key);
+    }
+
+    #[test]
+    fn test_validate_cycles() {
+        let scene = Scene::new(vec![Geometry::new_instance("a", vec![])], vec![], camera())
+            .with_definition("a", Geometry::new_instance("b", vec![]))
+            .with_definition(
+                "b",
+                Geometry::new_list(vec![Geometry::new_instance("a", vec![])]),
+            );
+
+        let diagnostics = validate(&scene);
+        assert_eq!(diagnostics[0].path, "definitions.a");
+        assert_eq!(
+            diagnostics[0].message,
+            "contains an instance of itself (a -> b -> a)"
+        );
+        assert_eq!(diagnostics[1].path, "definitions.b");
+    }
+
+    #[test]
+    fn test_textured_parameters() {
+        let key = |json: &str| {
+            let material: Material = serde_json::from_str(json).unwrap();
+            material.build(&mut Seeds::new(0)).unwrap().key()
+        };
+
+        // a constant is the same as a solid texture of it
+        let constant = r#"{"Metal": {"color": {"r": 0.8, "g": 0.6, "b": 0.2}, "fuzz": 0.3}}"#;
+        let solid = r#"{"Metal": {
+            "color": {"SolidColor": {"r": 0.8, "g": 0.6, "b": 0.2}},
+            "fuzz": {"SolidColor": {"r": 0.3, "g": 0.3, "b": 0.3}}
+        }}"#;
+        assert_eq!(key(constant), key(solid));
+
+        // a roughness map read from one channel of a checker
+        let rough = r#"{"Conductor": {"ior": "Gold", "roughness": {"Grayscale": {
+            "texture": {"Checker": {
+                "scale": 1.0,
+                "even": {"SolidColor": {"r": 0.0, "g": 0.1, "b": 0.0}},
+                "odd": {"SolidColor": {"r": 0.0, "g": 0.7, "b": 0.0}}
+            }},
+            "channel": "Green"
+        }}}}"#;
+        let material: Material = serde_json::from_str(rough).unwrap();
+        assert!(matches!(
+            material,
+            Material::Conductor {
+                roughness: Param::Texture(_),
+                anisotropy: Param::Constant(_),
+                ..
+            }
+        ));
+        assert_ne!(key(rough), key(r#"{"Conductor": {"ior": "Gold"}}"#));
+
+        // constants are still checked, and textures in place of them too
+        let grey = Material::new_lamb_solid(Color::new(0.5, 0.5, 0.5));
+        let world = vec![
+            Geometry::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, grey.clone()),
+            Geometry::new_sphere(
+                Point3::new(0.0, 0.0, 0.0),
+                1.0,
+                serde_json::from_str(r#"{"ThinGlass": {"ior": -1.0}}"#).unwrap(),
+            ),
+            Geometry::new_sphere(
+                Point3::new(0.0, 0.0, 0.0),
+                1.0,
+                serde_json::from_str(
+                    r#"{"Glass": {"ior": 1.5, "roughness": {"Checker": {
+                    "scale": 0.0,
+                    "even": {"SolidColor": {"r": 0.0, "g": 0.0, "b": 0.0}},
+                    "odd": {"SolidColor": {"r": 1.0, "g": 1.0, "b": 1.0}}
+                }}}}"#,
+                )
+                .unwrap(),
+            ),
+        ];
+        let scene = Scene::new(
+            world,
+            vec![Geometry::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, grey)],
+            camera(),
+        );
+        let paths: Vec<_> = validate(&scene).into_iter().map(|d| d.path).collect();
+        assert_eq!(
+            paths,
+            [
+                "world[1].Sphere.material.ThinGlass.ior",
+                "world[2].Sphere.material.Glass.roughness.Checker.scale",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dielectric_dispersion() {
+        let key = |json: &str| {
+            let material: Material = serde_json::from_str(json).unwrap();
+            material.build(&mut Seeds::new(0)).unwrap().key()
+        };
+
+        // a plain number is still clear glass, and the formulas of `Glass` work too
+        assert_eq!(
+            key(r#"{"Dielectric": 1.5}"#),
+            Material::new_dielectric(1.5)
+                .build(&mut Seeds::new(0))
+                .unwrap()
+                .key()
+        );
+        let cauchy = r#"{"Dielectric": {"Cauchy": {"a": 1.5, "b": 0.004}}}"#;
+        assert_ne!(key(cauchy), key(r#"{"Dielectric": 1.5}"#));
+
+        let scene = Scene::new(
+            vec![Geometry::new_sphere(
+                Point3::new(0.0, 0.0, 0.0),
+                1.0,
+                serde_json::from_str(r#"{"Dielectric": {"Cauchy": {"a": -1.0, "b": 0.0}}}"#)
+                    .unwrap(),
+            )],
+            vec![],
+            camera(),
+        );
+        let paths: Vec<_> = validate(&scene).into_iter().map(|d| d.path).collect();
+        assert_eq!(paths, ["world[0].Sphere.material.Dielectric", "lights"]);
+    }
+
+    #[test]
+    fn test_textured_ior() {
+        let glass = |kind: &str, ior: &str| -> Result<Material, _> {
+            serde_json::from_str(&format!(r#"{{"{}": {{"ior": {}}}}}"#, kind, ior))
+        };
+        let black = r#"{"SolidColor": {"r": 0.0, "g": 0.0, "b": 0.0}}"#;
+
+        // a texture needs the range of indices it maps to, and one that reaches below 1 is
+        // rejected
+        assert!(glass("ThinGlass", black).is_err());
+        let below = format!(r#"{{"texture": {}, "min": 0.0, "max": 1.5}}"#, black);
+        let backwards = format!(r#"{{"texture": {}, "max": 0.5}}"#, black);
+        let fine = format!(r#"{{"texture": {}, "max": 1.5}}"#, black);
+        let sphere = |material| Geometry::new_sphere(Point3::new(0.0, 0.0, 0.0), 1.0, material);
+        let scene = Scene::new(
+            vec![
+                sphere(glass("ThinGlass", &below).unwrap()),
+                sphere(glass("Glass", &backwards).unwrap()),
+                sphere(glass("Glass", &fine).unwrap()),
+            ],
+            vec![sphere(Material::new_lamb_solid(Color::new(0.5, 0.5, 0.5)))],
+            camera(),
+        );
+        let paths: Vec<_> = validate(&scene).into_iter().map(|d| d.path).collect();
+        assert_eq!(
+            paths,
+            [
+                "world[0].Sphere.material.ThinGlass.ior.min",
+                "world[1].Sphere.material.Glass.ior.max",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_noise_seeds() {
+        let noise: Material = serde_json::from_str(r#"{"Lambertian": {"Noise": 4.0}}"#).unwrap();
+        let keys = |seed| {
+            let mut seeds = Seeds::new(seed);
+            [0, 1].map(|_| noise.clone().build(&mut seeds).unwrap().key())
+        };
+
+        // each noise texture differs, and follows the scene's seed
+        let [first, second] = keys(0);
+        assert_ne!(first, second);
+        assert_eq!(keys(0), [first, second]);
+        assert_ne!(keys(1)[0], first);
+    }
 }
@@ -1,8 +1,10 @@
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{color::Color, texture};
+use crate::{color::Color, random, texture};
+
+use super::{validate::Validator, SceneError};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Texture {
@@ -14,6 +16,34 @@ pub enum Texture {
     },
     Image(String),
     Noise(f64),
+    /// One number from another texture, such as a roughness map packed into an image's green
+    /// channel.
+    Grayscale {
+        texture: Box<Texture>,
+        #[serde(default)]
+        channel: Channel,
+    },
+}
+
+/// Which number a grayscale texture reads from each color.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Channel {
+    #[default]
+    Luminance,
+    Red,
+    Green,
+    Blue,
+}
+
+impl From<Channel> for texture::Channel {
+    fn from(value: Channel) -> Self {
+        match value {
+            Channel::Luminance => texture::Channel::Luminance,
+            Channel::Red => texture::Channel::Red,
+            Channel::Green => texture::Channel::Green,
+            Channel::Blue => texture::Channel::Blue,
+        }
+    }
 }
 
 impl Texture {
@@ -36,17 +66,150 @@ impl Texture {
     pub fn new_noise(scale: f64) -> Self {
         Texture::Noise(scale)
     }
-}
 
-impl Into<crate::texture::Texture> for Texture {
-    fn into(self) -> crate::texture::Texture {
+    pub fn new_grayscale(texture: Texture, channel: Channel) -> Self {
+        Texture::Grayscale {
+            texture: Box::new(texture),
+            channel,
+        }
+    }
+
+    pub(super) fn validate(&self, path: &str, v: &mut Validator) {
         match self {
-            Texture::SolidColor(c) => texture::SolidColor::new(c),
             Texture::Checker { scale, even, odd } => {
-                texture::Checker::new(scale, Arc::new((*even).into()), Arc::new((*odd).into()))
+                let path = format!("{}.Checker", path);
+                if *scale <= 0.0 {
+                    v.report(
+                        &format!("{}.scale", path),
+                        format!("must be positive, got {}", scale),
+                    );
+                }
+                even.validate(&format!("{}.even", path), v);
+                odd.validate(&format!("{}.odd", path), v);
+            }
+            Texture::Image(filename) => {
+                if !Path::new(filename).is_file() {
+                    v.report(
+                        &format!("{}.Image", path),
+                        format!("{} not found", filename),
+                    );
+                }
+            }
+            Texture::Grayscale { texture, .. } => {
+                texture.validate(&format!("{}.Grayscale.texture", path), v)
             }
-            Texture::Image(filename) => texture::Image::new(&filename),
-            Texture::Noise(scale) => texture::Noise::new(scale),
+            Texture::SolidColor(_) | Texture::Noise(_) => {}
+        }
+    }
+}
+
+impl Texture {
+    pub(super) fn build(self, seeds: &mut Seeds) -> Result<crate::texture::Texture, SceneError> {
+        Ok(match self {
+            Texture::SolidColor(c) => texture::SolidColor::new(c),
+            Texture::Checker { scale, even, odd } => texture::Checker::new(
+                scale,
+                Arc::new((*even).build(seeds)?),
+                Arc::new((*odd).build(seeds)?),
+            ),
+            Texture::Image(filename) => {
+                texture::Image::new(&filename).map_err(|source| SceneError::Texture {
+                    path: filename.clone(),
+                    source,
+                })?
+            }
+            Texture::Noise(scale) => texture::Noise::new(scale, seeds.next_noise()),
+            Texture::Grayscale { texture, channel } => {
+                texture::Grayscale::new(Arc::new((*texture).build(seeds)?), channel.into())
+            }
+        })
+    }
+}
+
+/// Seeds each noise texture of a scene from the scene's seed and the texture's place in the order
+/// the scene is built, so that noises differ from each other but not between runs.
+pub(super) struct Seeds {
+    seed: u64,
+    noise: u64,
+}
+
+impl Seeds {
+    pub(super) fn new(seed: u64) -> Self {
+        Seeds { seed, noise: 0 }
+    }
+
+    fn next_noise(&mut self) -> u64 {
+        self.noise += 1;
+        random::hash(&[self.seed, self.noise])
+    }
+}
+
+/// A material parameter: the same everywhere, or read from a texture at each point.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Param<T> {
+    Constant(T),
+    Texture(Texture),
+}
+
+/// A number, or a texture whose luminance gives it at each point, exactly for a grey.
+pub type Scalar = Param<f64>;
+
+/// A color, or a texture.
+pub type Rgb = Param<Color>;
+
+impl<T> Param<T> {
+    /// Validates a texture, or a constant by `check`.
+    pub(super) fn validate_by(
+        &self,
+        path: &str,
+        v: &mut Validator,
+        check: impl FnOnce(&T, &mut Validator),
+    ) {
+        match self {
+            Param::Constant(c) => check(c, v),
+            Param::Texture(t) => t.validate(path, v),
+        }
+    }
+}
+
+impl Default for Scalar {
+    fn default() -> Self {
+        Scalar::Constant(0.0)
+    }
+}
+
+impl Scalar {
+    /// Validates a number from 0 to 1.
+    pub(super) fn validate(&self, path: &str, v: &mut Validator) {
+        self.validate_by(path, v, |c, v| {
+            if !(0.0..=1.0).contains(c) {
+                v.report(path, format!("must be between 0 and 1, got {}", c));
+            }
+        });
+    }
+}
+
+impl Rgb {
+    pub(super) fn validate(&self, path: &str, v: &mut Validator) {
+        self.validate_by(path, v, |_, _| {});
+    }
+}
+
+impl Scalar {
+    pub(super) fn build(self, seeds: &mut Seeds) -> Result<crate::texture::Texture, SceneError> {
+        match self {
+            Scalar::Constant(c) => Ok(texture::SolidColor::new(Color::new(c, c, c))),
+            Scalar::Texture(t) => t.build(seeds),
+        }
+    }
+}
+
+impl Rgb {
+    pub(super) fn build(self, seeds: &mut Seeds) -> Result<crate::texture::Texture, SceneError> {
+        match self {
+            Rgb::Constant(c) => Ok(texture::SolidColor::new(c)),
+            Rgb::Texture(t) => t.build(seeds),
         }
     }
 }
@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::{Geometry, Scene};
+
+/// A problem found by `validate`, located by the JSON path of the offending value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Checks a scene for values that would load but not render: degenerate geometry, singular
+/// transforms, a camera without a view direction, lights that cannot be sampled and so on.
+/// Every problem is reported, not just the first.
+pub fn validate(scene: &Scene) -> Vec<Diagnostic> {
+    let mut v = Validator {
+        definitions: &scene.definitions,
+        diagnostics: vec![],
+    };
+
+    for (name, geometry) in &scene.definitions {
+        geometry.validate(&format!("definitions.{}", name), &mut v);
+    }
+    for name in scene.definitions.keys() {
+        if let Some(cycle) = v.cycle(name) {
+            v.report(
+                &format!("definitions.{}", name),
+                format!("contains an instance of itself ({})", cycle.join(" -> ")),
+            );
+        }
+    }
+
+    for (i, geometry) in scene.world.iter().enumerate() {
+        geometry.validate(&format!("world[{}]", i), &mut v);
+    }
+
+    if scene.lights.is_empty() {
+        v.report(
+            "lights",
+            "at least one light is needed to sample direct lighting",
+        );
+    }
+    for (i, geometry) in scene.lights.iter().enumerate() {
+        let path = format!("lights[{}]", i);
+        geometry.validate(&path, &mut v);
+        if !geometry.can_sample(&scene.definitions, &mut Vec::new()) {
+            v.report(
+                &format!("{}.{}", path, geometry.kind()),
+                "lights must be spheres, quads, triangles, boxes, transforms or instances of them, or \
+                 non-empty lists of them",
+            );
+        }
+    }
+
+    scene.camera.validate("camera", &mut v);
+    v.diagnostics
+}
+
+pub(super) struct Validator<'a> {
+    definitions: &'a BTreeMap<String, Geometry>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    pub(super) fn report(&mut self, path: &str, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            path: path.to_owned(),
+            message: message.into(),
+        });
+    }
+
+    pub(super) fn has_definition(&self, name: &str) -> bool {
+        self.definitions.contains_key(name)
+    }
+
+    /// The chain of definitions leading from `name` back to itself, if there is one.
+    fn cycle(&self, name: &str) -> Option<Vec<String>> {
+        let mut chain = vec![name.to_owned()];
+        if self.reaches(name, name, &mut chain) {
+            Some(chain)
+        } else {
+            None
+        }
+    }
+
+    fn reaches(&self, from: &str, target: &str, chain: &mut Vec<String>) -> bool {
+        let Some(geometry) = self.definitions.get(from) else {
+            return false;
+        };
+        let mut references = vec![];
+        geometry.references(&mut references);
+        for r in references {
+            chain.push(r.to_owned());
+            if r == target {
+                return true;
+            }
+            // stop at definitions already on the chain; their own cycles are reported separately
+            if !chain[..chain.len() - 1].iter().any(|c| c == r) && self.reaches(r, target, chain) {
+                return true;
+            }
+            chain.pop();
+        }
+        false
+    }
+}
@@ -0,0 +1,394 @@
+//! Wavelengths of light, for spectral rendering.
+//!
+//! In spectral mode each camera sample traces four wavelengths at once: a hero wavelength drawn
+//! roughly in proportion to how visible it is, and three more rotated from it through the
+//! visible range, as in Wilkie et al.'s hero wavelength sampling. Every reflectance,
+//! transmittance and emission a path meets is given in RGB; it is turned into a smooth spectrum
+//! by Smits' method and evaluated at those wavelengths, and the camera turns the spectrum the
+//! path brings back into RGB through the CIE matching functions. Dispersive materials bend light
+//! by the hero wavelength. The other three would leave along other directions, so they drop out
+//! of the path there and the hero carries it on its own.
+
+use std::{
+    ops::{Add, Div, Mul},
+    sync::Arc,
+};
+
+use crate::{
+    color::{self, Color, XYZ_TO_SRGB},
+    point3::Point3,
+    random,
+    texture::Texture,
+};
+
+/// The visible range, in nanometres.
+pub const MIN_WAVELENGTH: f64 = 360.0;
+pub const MAX_WAVELENGTH: f64 = 830.0;
+
+/// The wavelength dispersive materials use outside spectral mode: the helium d line, at which
+/// glasses' indices are usually quoted.
+pub const REFERENCE_WAVELENGTH: f64 = 587.56;
+
+/// The wavelengths each camera sample traces.
+pub const SAMPLES: usize = 4;
+
+/// The integrals of the sRGB matching functions over the visible range, which make light with
+/// the same power at every wavelength come out white.
+const RGB_INTEGRALS: [f64; 3] = [128.335_162, 101.543_786, 97.116_889];
+
+/// The wavelengths of a camera sample, in nanometres, with the hero first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+    pub nm: [f64; SAMPLES],
+    /// Whether the path has dispersed, leaving only the hero.
+    dispersed: bool,
+}
+
+impl Wavelengths {
+    /// The wavelengths for `u`, between 0 and 1: the hero drawn from `u`, and the others from
+    /// `u` rotated by a quarter, a half and three quarters.
+    pub fn sample(u: f64) -> Wavelengths {
+        let mut nm = [0.0; SAMPLES];
+        for (i, nm) in nm.iter_mut().enumerate() {
+            *nm = sample_visible((u + i as f64 / SAMPLES as f64).fract());
+        }
+        Wavelengths {
+            nm,
+            dispersed: false,
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.nm[0]
+    }
+
+    /// The wavelengths of a path that has gone through a dispersive boundary, where only the hero
+    /// carries on.
+    pub fn disperse(self) -> Wavelengths {
+        Wavelengths {
+            dispersed: true,
+            ..self
+        }
+    }
+
+    /// The weight of the light arriving along a path that carried on from these wavelengths with
+    /// `next`. Where the others drop out the hero stands in for all of them, each of which
+    /// would have been drawn in the same way.
+    pub fn dispersion(&self, next: &Wavelengths) -> Spectrum {
+        let mut weight = Spectrum::constant(1.0);
+        if next.dispersed && !self.dispersed {
+            weight.0 = [0.0; SAMPLES];
+            weight.0[0] = SAMPLES as f64;
+        }
+        weight
+    }
+
+    /// The RGB color of `radiance`, the spectrum a path brings back at these wavelengths: each
+    /// wavelength's matching functions over their integrals and its pdf, averaged.
+    pub fn to_rgb(&self, radiance: &Spectrum) -> Color {
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for (&nm, &l) in self.nm.iter().zip(&radiance.0) {
+            let pdf = visible_pdf(nm);
+            if pdf <= 0.0 || l == 0.0 {
+                continue;
+            }
+            let rgb = color::transform(&XYZ_TO_SRGB, &cie_xyz(nm));
+            let weight = Color::new(
+                rgb.r / RGB_INTEGRALS[0],
+                rgb.g / RGB_INTEGRALS[1],
+                rgb.b / RGB_INTEGRALS[2],
+            );
+            sum += &(weight * (l / pdf));
+        }
+        sum / SAMPLES as f64
+    }
+}
+
+/// A wavelength drawn from `u`, between 0 and 1, roughly in proportion to how visible it is,
+/// from pbrt's `SampleVisibleWavelengths`.
+fn sample_visible(u: f64) -> f64 {
+    538.0 - 138.888_889 * (0.856_910_62 - 1.827_501_97 * u).atanh()
+}
+
+/// The density `sample_visible` draws `nm` with.
+fn visible_pdf(nm: f64) -> f64 {
+    if !(MIN_WAVELENGTH..=MAX_WAVELENGTH).contains(&nm) {
+        return 0.0;
+    }
+    0.003_939_804_2 / (0.0072 * (nm - 538.0)).cosh().powi(2)
+}
+
+/// The values of a spectrum at the wavelengths of a path.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spectrum(pub [f64; SAMPLES]);
+
+impl Spectrum {
+    pub fn constant(v: f64) -> Spectrum {
+        Spectrum([v; SAMPLES])
+    }
+
+    /// `color` as a smooth spectrum, by Smits' method, at `wavelengths`. The spectrum of white is
+    /// 1 everywhere, so it serves for emission as well as reflectance.
+    pub fn from_rgb(color: &Color, wavelengths: &Wavelengths) -> Spectrum {
+        Spectrum(wavelengths.nm.map(|nm| smits(color, nm)))
+    }
+}
+
+impl Add for Spectrum {
+    type Output = Spectrum;
+
+    fn add(self, rhs: Spectrum) -> Spectrum {
+        Spectrum(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Mul for Spectrum {
+    type Output = Spectrum;
+
+    fn mul(self, rhs: Spectrum) -> Spectrum {
+        Spectrum(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl Div<f64> for Spectrum {
+    type Output = Spectrum;
+
+    fn div(self, rhs: f64) -> Spectrum {
+        Spectrum(self.0.map(|v| v / rhs))
+    }
+}
+
+/// Smits' basis spectra, from "An RGB-to-Spectrum Conversion for Reflectances", sampled in ten
+/// even bins from 380 to 720 nm.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// The value at `nm` of Smits' spectrum for `color`: as much white as the smallest channel,
+/// then as much of the secondary color of the two largest as the middle one has left over, then
+/// the rest of the largest as its primary. The bins at the ends carry on past them.
+fn smits(color: &Color, nm: f64) -> f64 {
+    let bin = (((nm - 380.0) / 34.0).floor().max(0.0) as usize).min(9);
+    let (r, g, b) = (color.r, color.g, color.b);
+    let mix =
+        |low: f64, (mid, secondary): (f64, &[f64; 10]), (high, primary): (f64, &[f64; 10])| {
+            low * SMITS_WHITE[bin] + (mid - low) * secondary[bin] + (high - mid) * primary[bin]
+        };
+    if r <= g && r <= b {
+        if g <= b {
+            mix(r, (g, &SMITS_CYAN), (b, &SMITS_BLUE))
+        } else {
+            mix(r, (b, &SMITS_CYAN), (g, &SMITS_GREEN))
+        }
+    } else if g <= r && g <= b {
+        if r <= b {
+            mix(g, (r, &SMITS_MAGENTA), (b, &SMITS_BLUE))
+        } else {
+            mix(g, (b, &SMITS_MAGENTA), (r, &SMITS_RED))
+        }
+    } else if r <= g {
+        mix(b, (r, &SMITS_YELLOW), (g, &SMITS_GREEN))
+    } else {
+        mix(b, (g, &SMITS_YELLOW), (r, &SMITS_RED))
+    }
+}
+
+/// The CIE 1931 colour matching functions at `nm`, as X, Y and Z in the red, green and blue
+/// channels, from the multi-lobe fit by Wyman, Sloan and Shirley.
+pub fn cie_xyz(nm: f64) -> Color {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let t = (nm - mean) / if nm < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    Color::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// The index of refraction of a dielectric, which may depend on the wavelength or the point on
+/// the surface.
+#[derive(Clone, Debug)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    /// The same at every wavelength, and read from a scalar texture that maps 0 to `min` and 1
+    /// to `max`. Never less than 1, whatever the texture holds.
+    Textured {
+        texture: Arc<Texture>,
+        min: f64,
+        max: f64,
+    },
+    /// Cauchy's equation `a + b / λ²`, with λ in micrometres.
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// The Sellmeier equation `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, with λ in micrometres, as glass
+    /// catalogues give it.
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl RefractiveIndex {
+    /// The index at `nm` nanometres, at the point `p` with texture coordinates `u` and `v`.
+    pub fn at(&self, nm: f64, u: f64, v: f64, p: &Point3) -> f64 {
+        let um2 = (nm / 1000.0).powi(2);
+        match self {
+            RefractiveIndex::Constant(n) => *n,
+            RefractiveIndex::Textured { texture, min, max } => {
+                (min + (max - min) * texture.scalar(u, v, p)).max(1.0)
+            }
+            RefractiveIndex::Cauchy { a, b } => a + b / um2,
+            RefractiveIndex::Sellmeier { b, c } => {
+                (1.0 + (0..3).map(|i| b[i] * um2 / (um2 - c[i])).sum::<f64>()).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(
+            self,
+            RefractiveIndex::Constant(_) | RefractiveIndex::Textured { .. }
+        )
+    }
+
+    /// A hash of the coefficients, equal for equal indices in every run.
+    pub fn key(&self) -> u64 {
+        match self {
+            RefractiveIndex::Constant(n) => n.to_bits(),
+            RefractiveIndex::Textured { texture, min, max } => {
+                random::hash(&[3, texture.key(), min.to_bits(), max.to_bits()])
+            }
+            RefractiveIndex::Cauchy { a, b } => random::hash(&[1, a.to_bits(), b.to_bits()]),
+            RefractiveIndex::Sellmeier { b, c } => random::hash(&[
+                2,
+                b[0].to_bits(),
+                b[1].to_bits(),
+                b[2].to_bits(),
+                c[0].to_bits(),
+                c[1].to_bits(),
+                c[2].to_bits(),
+            ]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{color::Color, point3::Point3, texture::SolidColor};
+
+    use super::{
+        RefractiveIndex, Spectrum, Wavelengths, MAX_WAVELENGTH, MIN_WAVELENGTH,
+        REFERENCE_WAVELENGTH,
+    };
+
+    /// The average RGB color of `radiance` over evenly spread camera samples, each carrying on
+    /// with `next` of its wavelengths.
+    fn average(
+        radiance: impl Fn(&Wavelengths) -> Spectrum,
+        next: impl Fn(Wavelengths) -> Wavelengths,
+    ) -> Color {
+        let n = 20_000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let w = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+            sum += &w.to_rgb(&(w.dispersion(&next(w)) * radiance(&w)));
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn test_wavelengths() {
+        // light with the same power at every wavelength comes out white on average, whether or
+        // not the hero has to carry it alone
+        for next in [|w| w, Wavelengths::disperse] {
+            let white = average(|_| Spectrum::constant(1.0), next);
+            for c in [white.r, white.g, white.b] {
+                assert!((c - 1.0).abs() < 1e-3, "{:?}", white);
+            }
+        }
+
+        // the rotated wavelengths cover the range evenly between them
+        let w = Wavelengths::sample(0.1);
+        assert!(w
+            .nm
+            .iter()
+            .all(|nm| (MIN_WAVELENGTH..MAX_WAVELENGTH).contains(nm)));
+        assert_eq!(w.hero(), w.nm[0]);
+        assert_eq!(
+            w.disperse().dispersion(&w.disperse()),
+            Spectrum::constant(1.0)
+        );
+    }
+
+    #[test]
+    fn test_rgb_spectra() {
+        // colors survive the trip through their spectra, and stay linear in their brightness
+        for color in [
+            Color::new(1.0, 1.0, 1.0),
+            Color::new(0.8, 0.6, 0.2),
+            Color::new(0.2, 0.4, 0.9),
+            Color::new(0.1, 0.7, 0.3),
+            Color::new(0.9, 0.1, 0.1),
+            Color::new(8.0, 6.0, 4.0),
+        ] {
+            let rgb = average(|w| Spectrum::from_rgb(&color, w), |w| w);
+            for (c, expected) in [(rgb.r, color.r), (rgb.g, color.g), (rgb.b, color.b)] {
+                assert!(
+                    (c - expected).abs() < 0.02 * color.r.max(color.g).max(color.b),
+                    "{:?} came back as {:?}",
+                    color,
+                    rgb
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_refractive_index() {
+        // Schott's N-BK7
+        let bk7 = RefractiveIndex::Sellmeier {
+            b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+            c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+        };
+        let at = |index: &RefractiveIndex, nm| index.at(nm, 0.0, 0.0, &Point3::new(0.0, 0.0, 0.0));
+        assert!((at(&bk7, REFERENCE_WAVELENGTH) - 1.5168).abs() < 1e-4);
+        assert!(at(&bk7, 450.0) > at(&bk7, 650.0));
+
+        let cauchy = RefractiveIndex::Cauchy { a: 1.5, b: 0.004 };
+        assert!((at(&cauchy, 500.0) - 1.516).abs() < 1e-12);
+
+        // a texture maps onto its range, and never gives an index below 1
+        let textured = |grey, min| RefractiveIndex::Textured {
+            texture: Arc::new(SolidColor::new(Color::new(grey, grey, grey))),
+            min,
+            max: 1.7,
+        };
+        assert!((at(&textured(0.5, 1.3), 500.0) - 1.5).abs() < 1e-12);
+        assert_eq!(at(&textured(0.0, 0.0), 500.0), 1.0);
+    }
+}
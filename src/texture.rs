@@ -2,7 +2,15 @@ use std::sync::Arc;
 
 use image::RgbImage;
 
-use crate::{color::Color, interval::Interval, perlin::Perlin, point3::Point3};
+use rand::SeedableRng;
+
+use crate::{
+    color::Color,
+    interval::Interval,
+    perlin::Perlin,
+    point3::Point3,
+    random::{self, SampleRng},
+};
 
 #[derive(Clone, Debug)]
 pub struct Checker {
@@ -14,12 +22,15 @@ pub struct Checker {
 #[derive(Clone, Debug)]
 pub struct Image {
     image: Arc<RgbImage>,
+    /// A hash of the pixels, worked out once when the image is loaded.
+    key: u64,
 }
 
 #[derive(Clone, Debug)]
 pub struct Noise {
     noise: Perlin,
     scale: f64,
+    seed: u64,
 }
 
 #[derive(Clone, Debug)]
@@ -27,12 +38,31 @@ pub struct SolidColor {
     color: Color,
 }
 
+/// Which number a `Grayscale` texture reads from each color.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Channel {
+    #[default]
+    Luminance,
+    Red,
+    Green,
+    Blue,
+}
+
+/// A single number read from another texture, such as the roughness packed into one channel of
+/// an image, as a grey.
+#[derive(Clone, Debug)]
+pub struct Grayscale {
+    texture: Arc<Texture>,
+    channel: Channel,
+}
+
 #[derive(Clone, Debug)]
 pub enum Texture {
     SolidColor(SolidColor),
     Checker(Checker),
     Image(Image),
     Noise(Noise),
+    Grayscale(Grayscale),
 }
 
 impl Texture {
@@ -42,17 +72,78 @@ impl Texture {
             Texture::Checker(checker) => checker.value(u, v, p),
             Texture::Image(image) => image.value(u, v, p),
             Texture::Noise(noise) => noise.value(u, v, p),
+            Texture::Grayscale(grayscale) => {
+                let grey = grayscale.scalar(u, v, p);
+                Color::new(grey, grey, grey)
+            }
         }
     }
+
+    /// The texture as a single number, such as a roughness map: the luminance of its color, which
+    /// is exactly the value of a grey.
+    pub fn scalar(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        match self {
+            Texture::Grayscale(grayscale) => grayscale.scalar(u, v, p),
+            _ => luminance(&self.value(u, v, p)),
+        }
+    }
+
+    /// A hash of the texture's parameters, equal for equal textures in every run.
+    pub fn key(&self) -> u64 {
+        match self {
+            Texture::SolidColor(c) => color_key(0, &c.color),
+            Texture::Checker(checker) => random::hash(&[
+                1,
+                checker.inv_scale.to_bits(),
+                checker.even.key(),
+                checker.odd.key(),
+            ]),
+            Texture::Image(image) => random::hash(&[2, image.key]),
+            Texture::Noise(noise) => random::hash(&[3, noise.scale.to_bits(), noise.seed]),
+            Texture::Grayscale(grayscale) => {
+                random::hash(&[4, grayscale.texture.key(), grayscale.channel as u64])
+            }
+        }
+    }
+}
+
+/// `Color::luminance` with the weights of red and blue taken relative to green's, so that they
+/// sum to exactly 1 and a grey gives its own value back rather than one rounded from it.
+fn luminance(c: &Color) -> f64 {
+    c.g + 0.2126 * (c.r - c.g) + 0.0722 * (c.b - c.g)
+}
+
+/// Hashes `tag` and the channels of `color`.
+fn color_key(tag: u64, color: &Color) -> u64 {
+    random::hash(&[tag, color.r.to_bits(), color.g.to_bits(), color.b.to_bits()])
 }
 
 impl SolidColor {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(color: Color) -> Texture {
         Texture::SolidColor(SolidColor { color })
     }
 }
 
+impl Grayscale {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(texture: Arc<Texture>, channel: Channel) -> Texture {
+        Texture::Grayscale(Grayscale { texture, channel })
+    }
+
+    fn scalar(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let c = self.texture.value(u, v, p);
+        match self.channel {
+            Channel::Luminance => luminance(&c),
+            Channel::Red => c.r,
+            Channel::Green => c.g,
+            Channel::Blue => c.b,
+        }
+    }
+}
+
 impl Checker {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(scale: f64, even: Arc<Texture>, odd: Arc<Texture>) -> Texture {
         Texture::Checker(Checker {
             inv_scale: 1.0 / scale,
@@ -84,14 +175,26 @@ impl Checker {
 }
 
 impl Image {
-    pub fn new(filename: &str) -> Texture {
-        Texture::Image(Image {
-            image: Arc::new(image::open(filename).unwrap().into_rgb8()),
-        })
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(filename: &str) -> image::ImageResult<Texture> {
+        let image = image::open(filename)?.into_rgb8();
+        let (width, height) = image.dimensions();
+        let words: Vec<u64> = [width as u64, height as u64]
+            .into_iter()
+            .chain(image.as_raw().chunks(8).map(|chunk| {
+                let mut bytes = [0; 8];
+                bytes[..chunk.len()].copy_from_slice(chunk);
+                u64::from_le_bytes(bytes)
+            }))
+            .collect();
+        Ok(Texture::Image(Image {
+            key: random::hash(&words),
+            image: Arc::new(image),
+        }))
     }
 
     fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
-        if self.image.dimensions().0 <= 0 {
+        if self.image.dimensions().0 == 0 {
             return Color::new(0.0, 1.0, 1.0);
         }
 
@@ -113,10 +216,13 @@ impl Image {
 }
 
 impl Noise {
-    pub fn new(scale: f64) -> Texture {
+    #[allow(clippy::new_ret_no_self)]
+    /// Turbulent marble at `scale`, from Perlin noise that `seed` picks.
+    pub fn new(scale: f64, seed: u64) -> Texture {
         Texture::Noise(Noise {
-            noise: Perlin::new(),
+            noise: Perlin::new(&mut SampleRng::seed_from_u64(seed)),
             scale,
+            seed,
         })
     }
 
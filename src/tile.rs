@@ -0,0 +1,141 @@
+/// A rectangle of pixels, `x0..x1` by `y0..y1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> Self {
+        Tile { x0, y0, x1, y1 }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, other: &Tile) -> bool {
+        self.x0 <= other.x0 && self.y0 <= other.y0 && other.x1 <= self.x1 && other.y1 <= self.y1
+    }
+}
+
+/// The order tiles are handed out in. Tiles are rendered in parallel, so this is the order they
+/// start in rather than the order they finish in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Left to right, top to bottom.
+    Scanline,
+    /// Outwards from the centre, where the subject usually is.
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, so consecutive tiles are neighbours and share what they hit.
+    Hilbert,
+}
+
+/// Splits `region` into tiles of at most `size` by `size` pixels, in `order`.
+pub fn split(region: Tile, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let nx = region.width().div_ceil(size);
+    let ny = region.height().div_ceil(size);
+
+    let mut cells: Vec<(u32, u32)> = (0..ny).flat_map(|y| (0..nx).map(move |x| (x, y))).collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let (cx, cy) = ((nx as f64 - 1.0) / 2.0, (ny as f64 - 1.0) / 2.0);
+            let key = |&(x, y): &(u32, u32)| {
+                let (dx, dy) = (x as f64 - cx, y as f64 - cy);
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| {
+                let (a, b) = (key(a), key(b));
+                a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+            });
+        }
+        TileOrder::Hilbert => {
+            let n = nx.max(ny).next_power_of_two();
+            cells.sort_by_key(|&(x, y)| hilbert_index(n, x, y));
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(x, y)| {
+            let x0 = region.x0 + x * size;
+            let y0 = region.y0 + y * size;
+            Tile::new(
+                x0,
+                y0,
+                (x0 + size).min(region.x1),
+                (y0 + size).min(region.y1),
+            )
+        })
+        .collect()
+}
+
+/// The distance along the Hilbert curve through an `n` by `n` grid, `n` a power of two, to cell
+/// (`x`, `y`).
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        d += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // rotate the quadrant so the curve continues where the last one ended
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split, Tile, TileOrder};
+
+    #[test]
+    fn test_split() {
+        let region = Tile::new(3, 5, 103, 75);
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = split(region, 16, order);
+            assert_eq!(tiles.len(), 7 * 5);
+
+            // every pixel of the region is in exactly one tile
+            let mut covered = vec![0; 100 * 70];
+            for t in &tiles {
+                assert!(region.contains(t));
+                for y in t.y0..t.y1 {
+                    for x in t.x0..t.x1 {
+                        covered[((y - 5) * 100 + x - 3) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&c| c == 1));
+        }
+
+        // the spiral starts in the middle
+        let spiral = split(Tile::new(0, 0, 50, 50), 10, TileOrder::Spiral);
+        assert_eq!(spiral[0], Tile::new(20, 20, 30, 30));
+
+        // each step along the Hilbert curve moves to a neighbouring tile
+        let hilbert = split(Tile::new(0, 0, 64, 64), 8, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.x0.abs_diff(b.x0) + a.y0.abs_diff(b.y0), 8);
+        }
+    }
+}
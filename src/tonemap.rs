@@ -0,0 +1,244 @@
+//! Turning rendered radiance into displayable colors.
+
+use crate::color::{self, Color, Matrix3, SRGB_TO_XYZ, XYZ_TO_SRGB};
+
+/// The curve that squeezes radiance above 1 into the displayable range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Operator {
+    /// Cuts every channel off at 1.
+    #[default]
+    Clip,
+    /// Reinhard's `L / (1 + L)` on luminance, which keeps hues but flattens highlights.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    Aces,
+    /// Troy Sobotka's AgX, in the polynomial approximation, which desaturates highlights
+    /// towards white instead of skewing their hue.
+    Agx,
+}
+
+/// Exposure, white balance and a tone curve, applied when an image is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMap {
+    operator: Operator,
+    /// Exposure and white balance, which are both linear.
+    matrix: Matrix3,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+        ToneMap::new(0.0, None, Operator::default())
+    }
+}
+
+impl ToneMap {
+    /// Scales radiance by 2^`exposure`, adapts the white of a `white_balance` Kelvin light
+    /// source to the D65 white of sRGB, if given, and then applies `operator`.
+    pub fn new(exposure: f64, white_balance: Option<f64>, operator: Operator) -> Self {
+        let mut matrix = white_balance.map_or(IDENTITY, adapt_to_d65);
+        for row in &mut matrix {
+            for v in row {
+                *v *= exposure.exp2();
+            }
+        }
+        ToneMap { operator, matrix }
+    }
+
+    /// The radiance after exposure and white balance, which is what the float formats store.
+    pub fn expose(&self, c: &Color) -> Color {
+        color::transform(&self.matrix, c)
+    }
+
+    /// The linear color, each channel between 0 and 1, to show for radiance `c`.
+    pub fn display(&self, c: &Color) -> Color {
+        let c = self.expose(c);
+        let c = match self.operator {
+            Operator::Clip => c,
+            Operator::Reinhard => {
+                let l = c.luminance();
+                if l > 0.0 {
+                    &c / (1.0 + l)
+                } else {
+                    c
+                }
+            }
+            Operator::Aces => aces(&c),
+            Operator::Agx => agx(&c),
+        };
+        let unit = |v: f64| v.clamp(0.0, 1.0);
+        Color::new(unit(c.r), unit(c.g), unit(c.b))
+    }
+}
+
+const IDENTITY: Matrix3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// The Bradford transform from CIE XYZ to cone responses.
+const BRADFORD: Matrix3 = [
+    [0.895_1, 0.266_4, -0.161_4],
+    [-0.750_2, 1.713_5, 0.036_7],
+    [0.038_9, -0.068_5, 1.029_6],
+];
+
+const BRADFORD_INVERSE: Matrix3 = [
+    [0.986_992_9, -0.147_054_3, 0.159_962_7],
+    [0.432_305_3, 0.518_360_3, 0.049_291_2],
+    [-0.008_528_7, 0.040_042_8, 0.968_486_7],
+];
+
+/// The chromaticity of D65.
+const D65: (f64, f64) = (0.312_71, 0.329_02);
+
+/// The linear sRGB matrix that makes the white of a `kelvin` light look white.
+fn adapt_to_d65(kelvin: f64) -> Matrix3 {
+    let cone = |(x, y): (f64, f64)| {
+        color::transform(&BRADFORD, &Color::new(x / y, 1.0, (1.0 - x - y) / y))
+    };
+    let (from, to) = (cone(white_point(kelvin)), cone(D65));
+    let scale = [
+        [to.r / from.r, 0.0, 0.0],
+        [0.0, to.g / from.g, 0.0],
+        [0.0, 0.0, to.b / from.b],
+    ];
+    let in_xyz = color::multiply(&BRADFORD_INVERSE, &color::multiply(&scale, &BRADFORD));
+    color::multiply(&XYZ_TO_SRGB, &color::multiply(&in_xyz, &SRGB_TO_XYZ))
+}
+
+/// The chromaticity of light at color temperature `kelvin`, from 1667 K to 25000 K. Up to 4000 K
+/// that's a black body, like a tungsten lamp, from Kim et al.'s cubic fit. Above it's the CIE
+/// daylight locus, which runs a little greener and passes through D65 at 6504 K.
+fn white_point(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    if t <= 4000.0 {
+        let x = -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910;
+        let (x2, x3) = (x * x, x * x * x);
+        let y = if t <= 2222.0 {
+            -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+        } else {
+            -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+        };
+        (x, y)
+    } else {
+        let x = if t <= 7000.0 {
+            -4.607_0e9 / t3 + 2.967_8e6 / t2 + 0.099_11e3 / t + 0.244_063
+        } else {
+            -2.006_4e9 / t3 + 1.901_8e6 / t2 + 0.247_48e3 / t + 0.237_040
+        };
+        (x, -3.0 * x * x + 2.870 * x - 0.275)
+    }
+}
+
+fn aces(c: &Color) -> Color {
+    const INPUT: Matrix3 = [
+        [0.597_19, 0.354_58, 0.048_23],
+        [0.076_00, 0.908_34, 0.015_66],
+        [0.028_40, 0.133_83, 0.837_77],
+    ];
+    const OUTPUT: Matrix3 = [
+        [1.604_75, -0.531_08, -0.073_67],
+        [-0.102_08, 1.108_13, -0.006_05],
+        [-0.003_27, -0.072_76, 1.076_02],
+    ];
+    let fit = |v: f64| {
+        (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+    };
+
+    let c = color::transform(&INPUT, c);
+    color::transform(&OUTPUT, &Color::new(fit(c.r), fit(c.g), fit(c.b)))
+}
+
+fn agx(c: &Color) -> Color {
+    const INSET: Matrix3 = [
+        [
+            0.842_479_062_253_094,
+            0.078_433_599_999_999_2,
+            0.079_223_745_147_764_3,
+        ],
+        [
+            0.042_328_242_261_012_3,
+            0.878_468_636_469_772,
+            0.079_166_127_460_543_4,
+        ],
+        [0.042_375_654_905_705_1, 0.078_433_6, 0.879_142_973_793_104],
+    ];
+    const OUTSET: Matrix3 = [
+        [
+            1.196_879_005_120_17,
+            -0.098_020_881_140_136_8,
+            -0.099_029_744_079_720_5,
+        ],
+        [
+            -0.052_896_851_757_456_2,
+            1.151_903_129_904_17,
+            -0.098_961_176_844_843_3,
+        ],
+        [
+            -0.052_971_635_514_443_8,
+            -0.098_043_450_117_124_1,
+            1.151_073_672_641_16,
+        ],
+    ];
+    const MIN_EV: f64 = -12.473_93;
+    const MAX_EV: f64 = 4.026_069;
+    let curve = |v: f64| {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.002_32
+    };
+
+    let c = color::transform(&INSET, c);
+    let c = color::transform(&OUTSET, &Color::new(curve(c.r), curve(c.g), curve(c.b)));
+    // the curve's output is display encoded
+    let linear = |v: f64| v.max(0.0).powf(2.2);
+    Color::new(linear(c.r), linear(c.g), linear(c.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::color::{self, Color, XYZ_TO_SRGB};
+
+    use super::{white_point, Operator, ToneMap};
+
+    #[test]
+    fn test_tone_map() {
+        let grey = Color::new(0.18, 0.18, 0.18);
+        let lamp = Color::new(15.0, 15.0, 15.0);
+
+        let clip = ToneMap::default();
+        assert_eq!(clip.display(&grey), grey);
+        assert_eq!(clip.display(&lamp), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(
+            ToneMap::new(1.0, None, Operator::Clip).display(&grey),
+            Color::new(0.36, 0.36, 0.36)
+        );
+
+        // every curve is increasing, keeps greys grey and has room above the lamp
+        for operator in [Operator::Reinhard, Operator::Aces, Operator::Agx] {
+            let tone_map = ToneMap::new(0.0, None, operator);
+            let mut last = 0.0;
+            for v in [0.01, 0.18, 1.0, 15.0] {
+                let c = tone_map.display(&Color::new(v, v, v));
+                assert!(c.r > last, "{:?} isn't increasing at {}", operator, v);
+                assert!((c.r - c.g).abs() < 1e-3 && (c.g - c.b).abs() < 1e-3);
+                last = c.r;
+            }
+            assert!(last < 1.0, "{:?} clips the lamp", operator);
+        }
+    }
+
+    #[test]
+    fn test_white_balance() {
+        // D65 is already white
+        let white = Color::new(1.0, 1.0, 1.0);
+        let c = ToneMap::new(0.0, Some(6504.0), Operator::Clip).expose(&white);
+        assert!((c.r - 1.0).abs() < 1e-3 && (c.g - 1.0).abs() < 1e-3 && (c.b - 1.0).abs() < 1e-3);
+
+        // the white of a tungsten light comes out neutral when balanced for it
+        let (x, y) = white_point(3200.0);
+        let tungsten = color::transform(&XYZ_TO_SRGB, &Color::new(x / y, 1.0, (1.0 - x - y) / y));
+        assert!(tungsten.r > 1.5 * tungsten.b);
+        let c = ToneMap::new(0.0, Some(3200.0), Operator::Clip).expose(&tungsten);
+        assert!((c.r - c.b).abs() < 1e-3 && (c.g - c.b).abs() < 1e-3);
+    }
+}
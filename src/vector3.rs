@@ -17,8 +17,7 @@ impl Vector3 {
         Vector3 { x, y, z }
     }
 
-    pub fn random(min: f64, max: f64) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random(min: f64, max: f64, rng: &mut impl Rng) -> Self {
         Vector3 {
             x: rng.gen_range(min..max),
             y: rng.gen_range(min..max),
@@ -26,17 +25,16 @@ impl Vector3 {
         }
     }
 
-    pub fn random_in_unit_sphere() -> Self {
+    pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Self {
         loop {
-            let p = Vector3::random(-1.0, 1.0);
+            let p = Vector3::random(-1.0, 1.0, rng);
             if p.length_squared() < 1.0 {
                 return p;
             }
         }
     }
 
-    pub fn random_in_unit_disk() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_unit_disk(rng: &mut impl Rng) -> Self {
         loop {
             let p = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
             if p.length_squared() < 1.0 {
@@ -45,8 +43,8 @@ impl Vector3 {
         }
     }
 
-    pub fn random_in_hemisphere(normal: &Vector3) -> Self {
-        let on_unit_sphere = Self::random_unit_vector();
+    pub fn random_in_hemisphere(normal: &Vector3, rng: &mut impl Rng) -> Self {
+        let on_unit_sphere = Self::random_unit_vector(rng);
         if on_unit_sphere.dot(normal) > 0.0 {
             on_unit_sphere
         } else {
@@ -54,8 +52,7 @@ impl Vector3 {
         }
     }
 
-    pub fn random_cosine_dir() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random_cosine_dir(rng: &mut impl Rng) -> Self {
         let r1: f64 = rng.gen_range(0.0..1.0);
         let r2: f64 = rng.gen_range(0.0..1.0);
 
@@ -67,8 +64,8 @@ impl Vector3 {
         Vector3 { x, y, z }
     }
 
-    pub fn random_unit_vector() -> Self {
-        Vector3::random_in_unit_sphere().unit_vector()
+    pub fn random_unit_vector(rng: &mut impl Rng) -> Self {
+        Vector3::random_in_unit_sphere(rng).unit_vector()
     }
 
     pub fn reflect(&self, n: &Vector3) -> Vector3 {
@@ -248,9 +245,9 @@ impl std::ops::Sub<&Point3> for &Vector3 {
 
     fn sub(self, rhs: &Point3) -> Self::Output {
         Point3 {
-            x: self.x + rhs.x,
-            y: self.y + rhs.y,
-            z: self.z + rhs.z,
+            x: self.x - rhs.x,
+            y: self.y - rhs.y,
+            z: self.z - rhs.z,
         }
     }
 }
@@ -259,7 +256,7 @@ impl std::ops::Sub<Point3> for &Vector3 {
     type Output = Point3;
 
     fn sub(self, rhs: Point3) -> Self::Output {
-        self + &rhs
+        self - &rhs
     }
 }
 
@@ -267,7 +264,7 @@ impl std::ops::Sub<Point3> for Vector3 {
     type Output = Point3;
 
     fn sub(self, rhs: Point3) -> Self::Output {
-        &self + &rhs
+        &self - &rhs
     }
 }
 
@@ -374,11 +371,11 @@ mod test {
         let y = &Vector3::new(0.0, 1.0, 0.0);
         let z = &Vector3::new(0.0, 0.0, 1.0);
 
-        assert_eq!(x.cross(&y), *z);
-        assert_eq!(y.cross(&x), -z);
-        assert_eq!(y.cross(&z), *x);
-        assert_eq!(z.cross(&y), -x);
-        assert_eq!(z.cross(&x), *y);
-        assert_eq!(x.cross(&z), -y);
+        assert_eq!(x.cross(y), *z);
+        assert_eq!(y.cross(x), -z);
+        assert_eq!(y.cross(z), *x);
+        assert_eq!(z.cross(y), -x);
+        assert_eq!(z.cross(x), *y);
+        assert_eq!(x.cross(z), -y);
     }
 }